@@ -0,0 +1,166 @@
+//! CdnClient is a typed client for the cdn's own api, enabled via the client feature. Like the
+//! webhook it only speaks plain http and opens a fresh connection for every request.
+
+use anyhow::{Context, Result};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::{AUTHORIZATION, HOST};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use tokio::net::TcpStream;
+
+use crate::{encode_key, ErrorCode};
+
+/// CdnClient sends requests to the cdn listening on an address such as "127.0.0.1:8080". Every
+/// segment of the names of files is percent encoded, just like signed_url does
+pub struct CdnClient {
+    authority: String,
+    token: Option<String>,
+}
+
+/// FileInfo is the metadata of a file as listed by /files
+#[derive(Deserialize, Clone, Debug)]
+pub struct FileInfo {
+    pub name: String,
+    pub size: u64,
+    /// last modification as RFC3339, e.g. 2024-10-14T08:03:59Z
+    pub modified: String,
+    pub sha256: String,
+    pub downloads: u64,
+    /// expiry as RFC3339, None for files kept indefinitely
+    #[serde(default)]
+    pub expires: Option<String>,
+    #[serde(default)]
+    pub content_type: Option<String>,
+}
+
+/// Listing is a page of files as returned by CdnClient::list
+#[derive(Clone, Debug)]
+pub struct Listing {
+    pub files: Vec<FileInfo>,
+    /// number of matching files, including those not on the page
+    pub total: usize,
+}
+
+/// Reply is a CdnResponse as received by the client
+#[derive(Deserialize)]
+struct Reply {
+    msg: String,
+    #[serde(default)]
+    code: Option<ErrorCode>,
+    #[serde(default)]
+    files: Option<Vec<FileInfo>>,
+    #[serde(default)]
+    total: Option<usize>,
+}
+
+/// ApiError is a request the cdn answered with an error status, callers can downcast the errors
+/// returned by CdnClient to it to branch on code
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    /// None if the body carried no code this client knows
+    pub code: Option<ErrorCode>,
+    pub msg: String,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cdn answered with {}: {}", self.status, self.msg)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl CdnClient {
+    pub fn new(authority: impl Into<String>) -> CdnClient {
+        CdnClient {
+            authority: authority.into(),
+            token: None,
+        }
+    }
+
+    /// with_token sends token as bearer token with every request, see Config::api_token
+    pub fn with_token(mut self, token: impl Into<String>) -> CdnClient {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// upload stores content as name, failing with a conflict if name is already taken
+    pub async fn upload(&self, name: &str, content: impl Into<Bytes>) -> Result<()> {
+        let path = format!("/file/{}", encode_key(name));
+        self.send(Method::POST, &path, content.into()).await?;
+        Ok(())
+    }
+
+    /// download returns the content of name
+    pub async fn download(&self, name: &str) -> Result<Bytes> {
+        let path = format!("/file/{}", encode_key(name));
+        self.send(Method::GET, &path, Bytes::new()).await
+    }
+
+    /// list returns the first page of the files whose names start with prefix, ordered by name
+    pub async fn list(&self, prefix: &str) -> Result<Listing> {
+        let prefix = form_urlencoded::byte_serialize(prefix.as_bytes()).collect::<String>();
+        let body = self
+            .send(
+                Method::GET,
+                &format!("/files?prefix={}", prefix),
+                Bytes::new(),
+            )
+            .await?;
+        let reply = serde_json::from_slice::<Reply>(&body).context("Malformed listing")?;
+        let files = reply.files.unwrap_or_default();
+        Ok(Listing {
+            total: reply.total.unwrap_or(files.len()),
+            files,
+        })
+    }
+
+    /// delete removes name from the store
+    pub async fn delete(&self, name: &str) -> Result<()> {
+        let path = format!("/file/{}", encode_key(name));
+        self.send(Method::DELETE, &path, Bytes::new()).await?;
+        Ok(())
+    }
+
+    /// send sends a request to path and returns the body of a successful response, responses
+    /// with an error status fail with ApiError
+    async fn send(&self, method: Method, path: &str, body: Bytes) -> Result<Bytes> {
+        let stream = TcpStream::connect(&self.authority)
+            .await
+            .with_context(|| format!("Failed to connect to {}", self.authority))?;
+        let (mut sender, conn) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        // drives the connection until the response is read and sender is dropped
+        tokio::spawn(conn);
+
+        let mut req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(HOST, &self.authority);
+        if let Some(token) = &self.token {
+            req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let res = sender.send_request(req.body(Full::new(body))?).await?;
+        let status = res.status();
+        let body = res.into_body().collect().await?.to_bytes();
+        if status.is_success() {
+            return Ok(body);
+        }
+        Err(match serde_json::from_slice::<Reply>(&body) {
+            Ok(reply) => ApiError {
+                status,
+                code: reply.code,
+                msg: reply.msg,
+            },
+            Err(_) => ApiError {
+                status,
+                code: None,
+                msg: String::from_utf8_lossy(&body).into_owned(),
+            },
+        }
+        .into())
+    }
+}
@@ -0,0 +1,65 @@
+//! Changes to the store, broadcast to every subscriber of /events. Subscribers falling more than
+//! CAPACITY events behind miss the oldest ones instead of holding up the handlers publishing them.
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+const CAPACITY: usize = 256;
+
+/// Action is what happened to a file, serialized in snake_case, e.g. "uploaded"
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// stored under a name that wasn't taken before
+    Uploaded,
+    /// replaced the content of an existing file
+    Updated,
+    /// deleted, evicted or expired
+    Deleted,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Uploaded => "uploaded",
+            Action::Updated => "updated",
+            Action::Deleted => "deleted",
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Event {
+    pub action: Action,
+    pub name: String,
+}
+
+/// Events publishes changes to the store to its current subscribers, None tells them to stop
+pub struct Events(broadcast::Sender<Option<Event>>);
+
+impl Default for Events {
+    fn default() -> Events {
+        Events(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl Events {
+    /// publish sends an event to every subscriber, without any it is dropped
+    pub fn publish(&self, action: Action, name: &str) {
+        if self.0.receiver_count() > 0 {
+            let _ = self.0.send(Some(Event {
+                action,
+                name: name.to_string(),
+            }));
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Option<Event>> {
+        self.0.subscribe()
+    }
+
+    /// close ends the subscriptions of everyone currently subscribed, e.g. on shutdown
+    pub fn close(&self) {
+        let _ = self.0.send(None);
+    }
+}
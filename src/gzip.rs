@@ -0,0 +1,512 @@
+//! Minimal gzip (RFC 1952) encoder, the deflate stream (RFC 1951) is a single block using the
+//! fixed huffman codes combined with a hash chain based LZ77 matcher. This trades some ratio for
+//! not pulling in a compression dependency, text and json still shrink considerably.
+//!
+//! The decoder handles everything a gzip encoder may produce: stored, fixed and dynamic blocks as
+//! well as several concatenated members. It decodes one bit at a time, which is slow but plenty
+//! for bodies bounded by Config::max_body_size.
+
+use DecompressError::Malformed;
+
+const WINDOW_SIZE: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// how many previous occurrences of a hash are checked for the longest match
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// crc32 computes the IEEE crc32 checksum used by the gzip trailer
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, b| {
+        CRC_TABLE[((crc ^ *b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// BitWriter packs values least significant bit first, as deflate requires
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, len: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += len;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// write_code writes a huffman code, which are defined most significant bit first
+    fn write_code(&mut self, code: u32, len: u32) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// write_literal emits a literal/length symbol using the fixed huffman code table
+fn write_literal(w: &mut BitWriter, symbol: u16) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => w.write_code(0x30 + symbol, 8),
+        144..=255 => w.write_code(0x190 + symbol - 144, 9),
+        256..=279 => w.write_code(symbol - 256, 7),
+        _ => w.write_code(0xC0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE
+        .iter()
+        .rposition(|b| *b as usize <= length)
+        .unwrap();
+    write_literal(w, 257 + code as u16);
+    w.write(
+        (length - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code] as u32,
+    );
+
+    let code = DIST_BASE
+        .iter()
+        .rposition(|b| *b as usize <= distance)
+        .unwrap();
+    w.write_code(code as u32, 5);
+    w.write(
+        (distance - DIST_BASE[code] as usize) as u32,
+        DIST_EXTRA[code] as u32,
+    );
+}
+
+fn hash(data: &[u8]) -> usize {
+    let v = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (v.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/// deflate compresses data into a raw deflate stream
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter {
+        out: Vec::with_capacity(data.len() / 2),
+        bits: 0,
+        count: 0,
+    };
+    // BFINAL=1, BTYPE=01 (fixed huffman codes)
+    w.write(1, 1);
+    w.write(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let insert = |head: &mut [usize], prev: &mut [usize], pos: usize| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash(&data[pos..]);
+            prev[pos % WINDOW_SIZE] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if pos + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(&data[pos..])];
+            let mut chain = 0;
+            while candidate != usize::MAX && pos - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                // slots are reused once the window wraps, stop before following a stale entry
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            write_match(&mut w, best_len, best_dist);
+            for p in pos..pos + best_len {
+                insert(&mut head, &mut prev, p);
+            }
+            pos += best_len;
+        } else {
+            write_literal(&mut w, data[pos] as u16);
+            insert(&mut head, &mut prev, pos);
+            pos += 1;
+        }
+    }
+
+    write_literal(&mut w, 256);
+    w.finish()
+}
+
+/// compress wraps the deflated data into a gzip member, including header and crc32 trailer
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // magic, CM=deflate, no flags, no mtime, no extra flags, unknown OS
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    out.extend(deflate(data));
+    out.extend(crc32(data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+/// order in which the code lengths of the code length alphabet are sent in dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+const MAX_BITS: usize = 15;
+
+/// DecompressError is returned for gzip data that can't be decompressed
+#[derive(Debug)]
+pub enum DecompressError {
+    /// the data isn't valid gzip, with what is wrong about it
+    Malformed(&'static str),
+    /// the decompressed data exceeds the limit passed to decompress
+    TooLarge,
+}
+
+impl std::fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecompressError::Malformed(reason) => write!(f, "malformed gzip data: {}", reason),
+            DecompressError::TooLarge => f.write_str("decompressed data exceeds the size limit"),
+        }
+    }
+}
+
+impl std::error::Error for DecompressError {}
+
+/// BitReader reads values least significant bit first, the counterpart of BitWriter
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, len: u32) -> Result<u32, DecompressError> {
+        while self.count < len {
+            let byte = *self.data.get(self.pos).ok_or(Malformed("unexpected end"))?;
+            self.pos += 1;
+            self.bits |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.bits & ((1 << len) - 1);
+        self.bits >>= len;
+        self.count -= len;
+        Ok(value)
+    }
+
+    /// align skips the bits left of the current byte, less than 8 are ever buffered
+    fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+}
+
+/// Huffman is a canonical huffman code, decoded by walking its codes length by length
+struct Huffman {
+    /// number of codes of each length
+    counts: [u16; MAX_BITS + 1],
+    /// symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// new builds the code assigning lengths[symbol] bits to every symbol, 0 leaves it unused
+    fn new(lengths: &[u8]) -> Result<Huffman, DecompressError> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for len in lengths {
+            counts[*len as usize] += 1;
+        }
+        counts[0] = 0;
+        // codes left unassigned, going negative means more codes than fit into the lengths
+        let mut left = 1i32;
+        for count in &counts[1..] {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(Malformed("oversubscribed huffman code"));
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, len) in lengths.iter().enumerate() {
+            if *len > 0 {
+                symbols[offsets[*len as usize] as usize] = symbol as u16;
+                offsets[*len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, r: &mut BitReader) -> Result<u16, DecompressError> {
+        // code read so far, first code of the current length and index of its symbol
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for count in &self.counts[1..] {
+            code |= r.read(1)? as i32;
+            let count = *count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Malformed("invalid huffman code"))
+    }
+}
+
+/// fixed_codes returns the literal/length and distance codes of blocks with fixed huffman codes
+fn fixed_codes() -> Result<(Huffman, Huffman), DecompressError> {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+/// dynamic_codes reads the literal/length and distance codes sent ahead of a dynamic block
+fn dynamic_codes(r: &mut BitReader) -> Result<(Huffman, Huffman), DecompressError> {
+    let literals = r.read(5)? as usize + 257;
+    let distances = r.read(5)? as usize + 1;
+    let code_lengths = r.read(4)? as usize + 4;
+    if literals > 286 || distances > 30 {
+        return Err(Malformed("too many codes"));
+    }
+    let mut lengths = [0u8; 19];
+    for symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[*symbol] = r.read(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&lengths)?;
+
+    // both codes are sent as one sequence, repeats may cross from one into the other
+    let mut lengths = vec![0u8; literals + distances];
+    let mut i = 0;
+    while i < lengths.len() {
+        let (len, repeat) = match code_length_code.decode(r)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 if i == 0 => return Err(Malformed("repeat without a previous length")),
+            16 => (lengths[i - 1], 3 + r.read(2)? as usize),
+            17 => (0, 3 + r.read(3)? as usize),
+            _ => (0, 11 + r.read(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err(Malformed("too many code lengths"));
+        }
+        lengths[i..i + repeat].fill(len);
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(Malformed("no end of block code"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literals])?,
+        Huffman::new(&lengths[literals..])?,
+    ))
+}
+
+/// inflate decompresses the raw deflate stream read by r, appending to out. Distances may only
+/// reach back to start, where the stream's output begins
+fn inflate(
+    r: &mut BitReader,
+    out: &mut Vec<u8>,
+    start: usize,
+    limit: usize,
+) -> Result<(), DecompressError> {
+    loop {
+        let last = r.read(1)? == 1;
+        match r.read(2)? {
+            0 => {
+                r.align();
+                let header = r
+                    .data
+                    .get(r.pos..r.pos + 4)
+                    .ok_or(Malformed("unexpected end"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(Malformed("stored block length mismatch"));
+                }
+                let end = r.pos + 4 + len as usize;
+                let content = r
+                    .data
+                    .get(r.pos + 4..end)
+                    .ok_or(Malformed("unexpected end"))?;
+                if out.len() + content.len() > limit {
+                    return Err(DecompressError::TooLarge);
+                }
+                out.extend_from_slice(content);
+                r.pos = end;
+            }
+            btype @ (1 | 2) => {
+                let (literals, distances) = match btype {
+                    1 => fixed_codes()?,
+                    _ => dynamic_codes(r)?,
+                };
+                loop {
+                    let symbol = literals.decode(r)? as usize;
+                    if symbol < 256 {
+                        if out.len() >= limit {
+                            return Err(DecompressError::TooLarge);
+                        }
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        break;
+                    }
+                    let code = symbol - 257;
+                    if code >= LENGTH_BASE.len() {
+                        return Err(Malformed("invalid length code"));
+                    }
+                    let len =
+                        LENGTH_BASE[code] as usize + r.read(LENGTH_EXTRA[code] as u32)? as usize;
+                    let code = distances.decode(r)? as usize;
+                    if code >= DIST_BASE.len() {
+                        return Err(Malformed("invalid distance code"));
+                    }
+                    let dist = DIST_BASE[code] as usize + r.read(DIST_EXTRA[code] as u32)? as usize;
+                    if dist > out.len() - start {
+                        return Err(Malformed("distance too far back"));
+                    }
+                    if out.len() + len > limit {
+                        return Err(DecompressError::TooLarge);
+                    }
+                    // matches may overlap the bytes they produce, so they are copied one by one
+                    for _ in 0..len {
+                        out.push(out[out.len() - dist]);
+                    }
+                }
+            }
+            _ => return Err(Malformed("invalid block type")),
+        }
+        if last {
+            r.align();
+            return Ok(());
+        }
+    }
+}
+
+/// decompress decodes every gzip member of data, failing with TooLarge as soon as the output
+/// would exceed limit bytes
+pub fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, DecompressError> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+
+    let mut out = Vec::new();
+    let mut pos = 0;
+    loop {
+        let header = data
+            .get(pos..pos + 10)
+            .ok_or(Malformed("truncated header"))?;
+        if header[..3] != [0x1f, 0x8b, 8] {
+            return Err(Malformed("not gzip deflate data"));
+        }
+        let flags = header[3];
+        pos += 10;
+        if flags & FEXTRA != 0 {
+            let len = data
+                .get(pos..pos + 2)
+                .ok_or(Malformed("truncated header"))?;
+            pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+        }
+        for flag in [FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                let len = data
+                    .get(pos..)
+                    .and_then(|rest| rest.iter().position(|b| *b == 0))
+                    .ok_or(Malformed("truncated header"))?;
+                pos += len + 1;
+            }
+        }
+        if flags & FHCRC != 0 {
+            pos += 2;
+        }
+        if pos > data.len() {
+            return Err(Malformed("truncated header"));
+        }
+
+        let start = out.len();
+        let mut r = BitReader {
+            data,
+            pos,
+            bits: 0,
+            count: 0,
+        };
+        inflate(&mut r, &mut out, start, limit)?;
+        let trailer = data
+            .get(r.pos..r.pos + 8)
+            .ok_or(Malformed("truncated trailer"))?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&out[start..]) || size != (out.len() - start) as u32 {
+            return Err(Malformed("checksum mismatch"));
+        }
+        pos = r.pos + 8;
+        if pos == data.len() {
+            return Ok(out);
+        }
+    }
+}
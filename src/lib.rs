@@ -0,0 +1,4361 @@
+//! rust_cdn serves files over http. All writes are made on disk, but all reads are performed
+//! from the in memory FileStore, which makes reads extremely fast.
+//!
+//! The binary wires everything together via Config::from_env and serve, which loads the store
+//! via init_store while already accepting connections. Embedders may do the same with their own
+//! Config or call the handlers, e.g. upload and download, directly.
+
+#[cfg(feature = "client")]
+pub mod client;
+pub mod events;
+mod gzip;
+pub mod log;
+mod metrics;
+mod msgpack;
+mod multipart;
+mod sha256;
+pub mod storage;
+mod tar;
+mod webhook;
+
+use anyhow::{Context, Result};
+use events::{Action, Events};
+use http_body_util::combinators::BoxBody;
+use log::{AccessLog, AccessLogFormat};
+use metrics::Lookup;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use storage::{LocalDisk, StorageBackend, StorageWriter};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use core::str;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::task::Poll;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use httpdate::HttpDate;
+use hyper::body::{Body, Bytes, Frame};
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, ACCEPT_RANGES,
+    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ALLOW,
+    AUTHORIZATION, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LENGTH,
+    CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    ORIGIN, RANGE, SERVER, VARY, WWW_AUTHENTICATE,
+};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use hyper_util::server::graceful::GracefulShutdown;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tracing::{error, field, info, info_span, warn, Instrument};
+
+/// bodies smaller than this are sent uncompressed, since gzip overhead outweighs the savings
+const GZIP_MIN_SIZE: usize = 1024;
+/// bodies larger than GZIP_MAX_SIZE are sent uncompressed, compressing takes long enough that it
+/// would hold up the response more than the transfer it saves
+const GZIP_MAX_SIZE: usize = 1024 * 1024;
+
+/// smallest read buffer hyper accepts, see Config::max_buf_size
+const MIN_BUF_SIZE: usize = 8192;
+
+/// how long open connections get to finish their requests once a shutdown signal was received
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// request headers allowed in CORS preflights that don't list Access-Control-Request-Headers
+const CORS_ALLOW_HEADERS: &str =
+    "authorization, content-type, content-encoding, range, if-none-match, if-match";
+/// response headers readable by cross origin clients besides the CORS safelisted ones
+const CORS_EXPOSE_HEADERS: &str =
+    "etag, content-range, content-length, x-request-id, x-content-sha256";
+/// seconds browsers may cache a CORS preflight response
+const CORS_MAX_AGE: u32 = 86400;
+
+/// names starting with RESERVED_PREFIX are used by the cdn itself and can't be uploaded
+const RESERVED_PREFIX: &str = ".cdn-";
+/// uploads are written to files prefixed with TEMP_PREFIX in the store directory before being
+/// renamed into place
+const TEMP_PREFIX: &str = ".cdn-upload-";
+/// expiries of files uploaded with a ttl, persisted as a json object of key to unix seconds
+const EXPIRIES_FILE: &str = ".cdn-expiries.json";
+/// content types given on upload, see save_metadata
+const CONTENT_TYPES_FILE: &str = ".cdn-content-types.json";
+/// how often expired files are swept out of the store
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// correlates a request across proxies and our logs
+const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+/// client ip as seen by proxies, see Config::trust_forwarded
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+/// hex encoded SHA-256 digest of the identity encoded content of a file
+const X_CONTENT_SHA256: HeaderName = HeaderName::from_static("x-content-sha256");
+
+/// longest name in bytes accepted for uploads, most filesystems limit paths to 4096 bytes
+const MAX_NAME_LENGTH: usize = 1024;
+
+/// page size of the /files listing if the client doesn't pass a limit
+const DEFAULT_PAGE_SIZE: usize = 100;
+/// upper bound for the limit of the /files listing
+const MAX_PAGE_SIZE: usize = 1000;
+/// number of files listed by /files/popular if the client doesn't pass a limit
+const DEFAULT_POPULAR_SIZE: usize = 10;
+
+/// FileStore maps the key of every file, its path relative to Config::store_dir, to the file
+pub type FileStore = Arc<RwLock<HashMap<String, File>>>;
+
+/// read_store acquires a read lock on the store. A panic while holding the write lock poisons the
+/// lock, but every write is a single insert or remove that leaves the map consistent, so the
+/// poison is ignored instead of failing every following request
+fn read_store(store: &FileStore) -> RwLockReadGuard<'_, HashMap<String, File>> {
+    store.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// write_store acquires the write lock on the store, recovering from poisoning like read_store
+fn write_store(store: &FileStore) -> RwLockWriteGuard<'_, HashMap<String, File>> {
+    store.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// File is an entry of the FileStore
+#[derive(Serialize, Clone)]
+pub struct File {
+    /// key of the file in the store, e.g. "css/app.css"
+    pub name: String,
+    /// content held in memory, None for files larger than Config::cache_max_size, which are read
+    /// from disk instead
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_content"
+    )]
+    pub content: Option<Bytes>,
+    /// size of the content in bytes
+    pub size: u64,
+    /// last modification of the file on disk, serialized as RFC3339
+    #[serde(serialize_with = "serialize_rfc3339")]
+    pub modified: SystemTime,
+    /// quoted entity tag of content, computed once when the file enters the store
+    #[serde(skip)]
+    pub etag: String,
+    /// hex encoded SHA-256 digest of content, for clients verifying the integrity of downloads
+    pub sha256: String,
+    /// number of times the content was downloaded, shared between all clones of the file so
+    /// downloads count while holding just the read lock. Reset once the file is replaced
+    #[serde(serialize_with = "serialize_downloads")]
+    pub downloads: Arc<AtomicU64>,
+    /// milliseconds since the unix epoch of the last download, or of the upload if there was none
+    /// yet. Shared between clones like downloads, least recently accessed files are evicted first
+    /// once Config::max_store_size is exceeded
+    #[serde(skip)]
+    pub accessed: Arc<AtomicU64>,
+    /// the file is deleted once expired, None for files kept indefinitely
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_expires"
+    )]
+    pub expires: Option<SystemTime>,
+    /// media type given when uploading, served instead of guessing it from the extension
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+impl File {
+    /// new builds a cached file from its full content
+    pub fn new(name: String, content: Vec<u8>, modified: SystemTime) -> File {
+        let sha256 = sha256::hex(&sha256::digest(&content));
+        File {
+            etag: etag(&sha256),
+            sha256,
+            downloads: Arc::default(),
+            accessed: Arc::new(AtomicU64::new(unix_millis(SystemTime::now()))),
+            size: content.len() as u64,
+            name,
+            content: Some(content.into()),
+            modified,
+            expires: None,
+            content_type: None,
+        }
+    }
+
+    /// expired reports whether the file is past its expiry at now
+    pub fn expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// touch marks the file as accessed just now
+    pub fn touch(&self) {
+        self.accessed
+            .store(unix_millis(SystemTime::now()), Ordering::Relaxed);
+    }
+
+    /// metadata copies everything but the content, for listings
+    pub fn metadata(&self) -> File {
+        File {
+            name: self.name.clone(),
+            content: None,
+            size: self.size,
+            modified: self.modified,
+            etag: self.etag.clone(),
+            sha256: self.sha256.clone(),
+            downloads: Arc::clone(&self.downloads),
+            accessed: Arc::clone(&self.accessed),
+            expires: self.expires,
+            content_type: self.content_type.clone(),
+        }
+    }
+
+    /// mime is the Content-Type the file is served with
+    pub fn mime(&self) -> &str {
+        self.content_type
+            .as_deref()
+            .unwrap_or_else(|| content_type(&self.name))
+    }
+
+    /// has_metadata reports whether the file carries metadata persisted via save_metadata
+    fn has_metadata(&self) -> bool {
+        self.expires.is_some() || self.content_type.is_some()
+    }
+}
+
+fn serialize_rfc3339<S: serde::Serializer>(
+    time: &SystemTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&rfc3339(*time))
+}
+
+fn serialize_expires<S: serde::Serializer>(
+    expires: &Option<SystemTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    expires.map(rfc3339).serialize(serializer)
+}
+
+fn serialize_downloads<S: serde::Serializer>(
+    downloads: &Arc<AtomicU64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(downloads.load(Ordering::Relaxed))
+}
+
+fn serialize_content<S: serde::Serializer>(
+    content: &Option<Bytes>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    content.as_deref().serialize(serializer)
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// rfc3339 formats time as an RFC3339 timestamp in UTC with second precision, e.g.
+/// 2024-10-14T08:03:59Z
+fn rfc3339(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+    // civil date from days since the epoch, see https://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// etag derives a strong entity tag from the hex sha256 digest of a file's content, so it
+/// stays the same across restarts and builds
+fn etag(sha256: &str) -> String {
+    format!("\"{}\"", sha256)
+}
+
+/// UploadMode decides how an upload treats an existing file of the same name
+#[derive(Clone, Copy)]
+pub enum UploadMode {
+    /// POST, fails if the file exists
+    Create,
+    /// POST with ?overwrite=true, creates or replaces the file
+    Overwrite,
+    /// PUT, fails if the file doesn't exist
+    Update,
+}
+
+/// UploadOptions are the settings of an upload taken from its query and headers
+struct UploadOptions<'a> {
+    mode: UploadMode,
+    /// etag the existing file has to match, from If-Match
+    if_match: Option<&'a str>,
+    ttl: Option<Duration>,
+    /// validate the upload without storing anything, from ?dry_run=true
+    dry_run: bool,
+}
+
+/// UploadRequest is a parsed upload, independent of the body format it was sent in
+struct UploadRequest {
+    name: String,
+    content: Vec<u8>,
+    content_type: Option<String>,
+}
+
+/// NAME_FIELDS and CONTENT_FIELDS are the field names accepted for the name and content of form
+/// and json uploads. Forms sending several take the first, json bodies are rejected as malformed.
+/// The aliases are what HTML forms and upload tools usually send
+const NAME_FIELDS: [&str; 2] = ["name", "filename"];
+const CONTENT_FIELDS: [&str; 2] = ["content", "file"];
+
+/// JsonUploadRequest is the body of an upload with a Content-Type of application/json, the
+/// aliases match NAME_FIELDS and CONTENT_FIELDS
+#[derive(Deserialize)]
+struct JsonUploadRequest {
+    #[serde(alias = "filename")]
+    name: String,
+    #[serde(alias = "file")]
+    content: String,
+    #[serde(default)]
+    content_type: Option<String>,
+}
+
+/// Config holds all settings resolved once at startup, see Config::from_env
+pub struct Config {
+    /// address the server listens on
+    pub addr: SocketAddr,
+    /// unix domain socket to listen on instead of addr
+    pub unix_socket: Option<PathBuf>,
+    /// directory all files are persisted to, relative paths are resolved against the working
+    /// directory
+    pub store_dir: PathBuf,
+    /// files up to this many bytes are held in memory, larger ones are served from disk
+    pub cache_max_size: u64,
+    /// origins allowed to access the cdn cross origin, "*" allows any origin
+    pub cors_origins: Vec<String>,
+    /// largest request body in bytes accepted for uploads
+    pub max_body_size: u64,
+    /// largest single file in bytes accepted for uploads, e.g. bounding each part of a multipart
+    /// upload. None only bounds files by max_body_size and max_store_size
+    pub max_file_size: Option<u64>,
+    /// once the sizes of all files add up to more than this many bytes, the least recently
+    /// accessed files are evicted to make room for uploads. None keeps files regardless of size
+    pub max_store_size: Option<u64>,
+    /// Cache-Control header sent with downloads
+    pub cache_control: String,
+    /// Server header sent with every response, None omits it
+    pub server_name: Option<HeaderValue>,
+    /// format answered requests are logged in
+    pub access_log: AccessLogFormat,
+    /// log the client named by X-Forwarded-For instead of the peer, only safe behind a proxy
+    /// overwriting the header
+    pub trust_forwarded: bool,
+    /// http url every stored file is announced to, see the webhook module
+    pub webhook_url: Option<hyper::Uri>,
+    /// list the known routes in 404 responses to unknown paths, meant for exploring the api
+    pub list_routes: bool,
+    /// keep files in memory only, store_dir is never read from or written to
+    pub memory_only: bool,
+    /// share the content of files with identical content instead of storing it twice
+    pub dedup: bool,
+    /// let downloads find files by names differing in case and reject uploads of names that only
+    /// differ in case from stored ones
+    pub case_insensitive: bool,
+    /// rescan store_dir for changes made outside the api this often, None disables rescanning
+    pub watch_interval: Option<Duration>,
+    /// close connections neither sending nor receiving anything for this long, e.g. stalled or
+    /// deliberately slow clients. None keeps connections open indefinitely
+    pub idle_timeout: Option<Duration>,
+    /// connections served at once, further connections aren't accepted until one closes and
+    /// queue up in the listen backlog meanwhile. None serves any number of connections
+    pub max_connections: Option<usize>,
+    /// keep connections open for further requests once a response was sent
+    pub keep_alive: bool,
+    /// headers accepted per request, requests with more are rejected with 431
+    pub max_headers: usize,
+    /// size of the buffer requests are read into, which bounds the size of the request head.
+    /// None keeps hyper's default of about 400KiB, smaller values have to be at least 8KiB
+    pub max_buf_size: Option<usize>,
+    /// send response headers in the case they were set in instead of lowercase
+    pub preserve_header_case: bool,
+    /// time clients get to send the head of a request, None waits indefinitely
+    pub header_read_timeout: Option<Duration>,
+    /// base64 encoded username:password required by mutating requests, None disables auth
+    pub basic_auth: Option<String>,
+    /// token required as "Authorization: Bearer <token>" by mutating requests, None disables it
+    pub api_token: Option<String>,
+    /// require basic_auth or api_token for reads as well, /health always stays public
+    pub auth_reads: bool,
+    /// secret downloads, including /file/:name/checksum and /file/:name/exists, have to be signed
+    /// with for name, see signed_url. None allows unsigned downloads
+    pub signing_secret: Option<String>,
+    /// backend the content of files is persisted to, None persists to store_dir via LocalDisk
+    pub storage: Option<Arc<dyn StorageBackend>>,
+    /// changes to the store, streamed to clients of /events
+    pub events: Events,
+}
+
+impl Config {
+    /// from_env resolves the configuration from the environment, falling back to defaults:
+    ///
+    /// - CDN_HOST: ipv4 or ipv6 address to bind to, :: listens on both ipv4 and ipv6 where the
+    ///   platform allows, defaults to 127.0.0.1
+    /// - CDN_PORT: port to bind to, defaults to 8080
+    /// - CDN_UNIX_SOCKET: path of a unix domain socket to listen on instead of CDN_HOST and
+    ///   CDN_PORT, unset by default
+    /// - CDN_STORE_DIR: directory files are stored in, defaults to ./store
+    /// - CDN_CACHE_MAX_SIZE: largest file in bytes kept in memory, defaults to 8MiB
+    /// - CDN_CORS_ORIGINS: comma separated origins allowed for CORS, defaults to *
+    /// - CDN_MAX_BODY_SIZE: largest upload body in bytes, defaults to 10MiB
+    /// - CDN_MAX_FILE_SIZE: largest single file in bytes, unlimited by default
+    /// - CDN_MAX_STORE_SIZE: total size in bytes of all files, beyond which the least recently
+    ///   downloaded files are evicted, unlimited by default
+    /// - CDN_CACHE_MAX_AGE: seconds downloads may be cached by clients and intermediaries, or
+    ///   no-store to disable caching, defaults to 3600
+    /// - CDN_MEMORY_ONLY: keep files in memory only without touching CDN_STORE_DIR, defaults to
+    ///   false
+    /// - CDN_DEDUP: store identical content only once, defaults to false
+    /// - CDN_CASE_INSENSITIVE: look up names case insensitively, defaults to false
+    /// - CDN_WATCH_INTERVAL: seconds between rescans of CDN_STORE_DIR for external changes, 0
+    ///   disables rescanning, defaults to 0
+    /// - CDN_IDLE_TIMEOUT: seconds after which silent connections are closed, 0 keeps them open,
+    ///   defaults to 60
+    /// - CDN_MAX_CONNECTIONS: connections served at once, 0 for no limit, defaults to 1024
+    /// - CDN_KEEP_ALIVE: reuse connections for further requests, defaults to true
+    /// - CDN_MAX_HEADERS: headers accepted per request, defaults to 100
+    /// - CDN_MAX_BUF_SIZE: bytes buffered while reading a request head, at least 8192, defaults
+    ///   to hyper's default
+    /// - CDN_PRESERVE_HEADER_CASE: keep the case of response headers, defaults to false
+    /// - CDN_HEADER_READ_TIMEOUT: seconds clients get to send a request head, 0 waits
+    ///   indefinitely, defaults to 30
+    /// - CDN_BASIC_AUTH: username:password required for uploads and deletes, unset by default
+    /// - CDN_API_TOKEN: bearer token accepted for uploads and deletes, unset by default
+    /// - CDN_AUTH_READS: require CDN_BASIC_AUTH or CDN_API_TOKEN for reads too, defaults to false
+    /// - CDN_SIGNING_SECRET: secret downloads have to be signed with, unset by default
+    /// - CDN_SERVER_NAME: value of the Server header, empty to omit it, defaults to
+    ///   rust_cdn/<version>
+    /// - CDN_ACCESS_LOG: format requests are logged in, one of pipe, common or json, defaults to
+    ///   pipe
+    /// - CDN_TRUST_FORWARDED: log the client ip from X-Forwarded-For, defaults to false
+    /// - CDN_WEBHOOK_URL: http url POSTed the name, size and sha256 of every stored file, unset by
+    ///   default
+    /// - CDN_LIST_ROUTES: list the known routes in 404 responses to unknown paths, defaults to
+    ///   false
+    pub fn from_env() -> Result<Config> {
+        let default = Config::default();
+        Ok(Config {
+            addr: bind_addr()?,
+            unix_socket: std::env::var_os("CDN_UNIX_SOCKET")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+            store_dir: std::env::var_os("CDN_STORE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or(default.store_dir),
+            cache_max_size: env_parse("CDN_CACHE_MAX_SIZE", default.cache_max_size)?,
+            cors_origins: match std::env::var("CDN_CORS_ORIGINS") {
+                Ok(origins) => origins
+                    .split(',')
+                    .map(|origin| origin.trim().to_string())
+                    .filter(|origin| !origin.is_empty())
+                    .collect(),
+                Err(_) => default.cors_origins,
+            },
+            max_body_size: env_parse("CDN_MAX_BODY_SIZE", default.max_body_size)?,
+            max_file_size: match std::env::var("CDN_MAX_FILE_SIZE") {
+                Ok(_) => Some(env_parse("CDN_MAX_FILE_SIZE", 0)?),
+                Err(_) => default.max_file_size,
+            },
+            max_store_size: match std::env::var("CDN_MAX_STORE_SIZE") {
+                Ok(_) => Some(env_parse("CDN_MAX_STORE_SIZE", 0)?),
+                Err(_) => default.max_store_size,
+            },
+            cache_control: match std::env::var("CDN_CACHE_MAX_AGE") {
+                Ok(value) if value.trim() == "no-store" => String::from("no-store"),
+                Ok(_) => format!(
+                    "public, max-age={}",
+                    env_parse::<u64>("CDN_CACHE_MAX_AGE", 0)?
+                ),
+                Err(_) => default.cache_control,
+            },
+            server_name: match std::env::var("CDN_SERVER_NAME") {
+                Ok(name) if name.is_empty() => None,
+                Ok(name) => Some(
+                    HeaderValue::from_str(&name)
+                        .with_context(|| format!("Invalid value '{}' for CDN_SERVER_NAME", name))?,
+                ),
+                Err(_) => default.server_name,
+            },
+            access_log: env_parse("CDN_ACCESS_LOG", default.access_log)?,
+            trust_forwarded: env_flag("CDN_TRUST_FORWARDED", default.trust_forwarded)?,
+            webhook_url: match std::env::var("CDN_WEBHOOK_URL") {
+                Ok(url) if !url.is_empty() => Some(
+                    webhook::parse_url(&url)
+                        .with_context(|| format!("Invalid value '{}' for CDN_WEBHOOK_URL", url))?,
+                ),
+                _ => None,
+            },
+            list_routes: env_flag("CDN_LIST_ROUTES", default.list_routes)?,
+            basic_auth: std::env::var("CDN_BASIC_AUTH")
+                .ok()
+                .filter(|credentials| !credentials.is_empty())
+                .map(|credentials| base64(credentials.as_bytes())),
+            api_token: std::env::var("CDN_API_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            auth_reads: env_flag("CDN_AUTH_READS", default.auth_reads)?,
+            signing_secret: std::env::var("CDN_SIGNING_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty()),
+            memory_only: env_flag("CDN_MEMORY_ONLY", default.memory_only)?,
+            dedup: env_flag("CDN_DEDUP", default.dedup)?,
+            case_insensitive: env_flag("CDN_CASE_INSENSITIVE", default.case_insensitive)?,
+            watch_interval: Some(Duration::from_secs(env_parse("CDN_WATCH_INTERVAL", 0)?))
+                .filter(|interval| !interval.is_zero()),
+            idle_timeout: Some(Duration::from_secs(env_parse(
+                "CDN_IDLE_TIMEOUT",
+                default.idle_timeout.map_or(0, |timeout| timeout.as_secs()),
+            )?))
+            .filter(|timeout| !timeout.is_zero()),
+            max_connections: Some(env_parse(
+                "CDN_MAX_CONNECTIONS",
+                default.max_connections.unwrap_or(0),
+            )?)
+            .filter(|max| *max > 0),
+            keep_alive: env_flag("CDN_KEEP_ALIVE", default.keep_alive)?,
+            max_headers: env_parse("CDN_MAX_HEADERS", default.max_headers)?,
+            max_buf_size: match std::env::var("CDN_MAX_BUF_SIZE") {
+                Ok(size) => match size.parse::<usize>() {
+                    Ok(size) if size >= MIN_BUF_SIZE => Some(size),
+                    _ => anyhow::bail!(
+                        "Invalid value '{}' for CDN_MAX_BUF_SIZE, expected at least {}",
+                        size,
+                        MIN_BUF_SIZE
+                    ),
+                },
+                Err(_) => default.max_buf_size,
+            },
+            preserve_header_case: env_flag(
+                "CDN_PRESERVE_HEADER_CASE",
+                default.preserve_header_case,
+            )?,
+            header_read_timeout: Some(Duration::from_secs(env_parse(
+                "CDN_HEADER_READ_TIMEOUT",
+                default
+                    .header_read_timeout
+                    .map_or(0, |timeout| timeout.as_secs()),
+            )?))
+            .filter(|timeout| !timeout.is_zero()),
+            storage: None,
+            events: default.events,
+        })
+    }
+
+    /// upload_limit is the size of the largest file accepted by uploads
+    pub fn upload_limit(&self) -> u64 {
+        self.max_body_size
+            .min(self.max_file_size.unwrap_or(u64::MAX))
+            .min(self.max_store_size.unwrap_or(u64::MAX))
+    }
+
+    /// storage returns the backend files are persisted to
+    pub fn storage(&self) -> Arc<dyn StorageBackend> {
+        match &self.storage {
+            Some(storage) => Arc::clone(storage),
+            None => Arc::new(LocalDisk::new(&self.store_dir)),
+        }
+    }
+}
+
+impl Default for Config {
+    /// default is the configuration used if none of the environment variables read by
+    /// Config::from_env are set
+    fn default() -> Config {
+        Config {
+            addr: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            unix_socket: None,
+            store_dir: PathBuf::from("./store"),
+            cache_max_size: 8 * 1024 * 1024,
+            cors_origins: vec![String::from("*")],
+            max_body_size: 10 * 1024 * 1024,
+            max_file_size: None,
+            max_store_size: None,
+            cache_control: String::from("public, max-age=3600"),
+            server_name: Some(HeaderValue::from_static(concat!(
+                "rust_cdn/",
+                env!("CARGO_PKG_VERSION")
+            ))),
+            access_log: AccessLogFormat::Pipe,
+            trust_forwarded: false,
+            webhook_url: None,
+            list_routes: false,
+            memory_only: false,
+            dedup: false,
+            case_insensitive: false,
+            watch_interval: None,
+            idle_timeout: Some(Duration::from_secs(60)),
+            max_connections: Some(1024),
+            keep_alive: true,
+            max_headers: 100,
+            max_buf_size: None,
+            preserve_header_case: false,
+            header_read_timeout: Some(Duration::from_secs(30)),
+            basic_auth: None,
+            api_token: None,
+            auth_reads: false,
+            signing_secret: None,
+            storage: None,
+            events: Events::default(),
+        }
+    }
+}
+
+/// env_flag parses the boolean environment variable key, accepting true/false and 1/0
+fn env_flag(key: &str, default: bool) -> Result<bool> {
+    match std::env::var(key) {
+        Ok(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" => Ok(true),
+            "0" | "false" => Ok(false),
+            _ => anyhow::bail!("Invalid value '{}' for {}", value, key),
+        },
+        Err(_) => Ok(default),
+    }
+}
+
+/// env_parse parses the environment variable key, falling back to default if it isn't set
+fn env_parse<T>(key: &str, default: T) -> Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::var(key) {
+        Ok(value) => value
+            .parse()
+            .with_context(|| format!("Invalid value '{}' for {}", value, key)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// CdnResponse is the json body of every response not carrying file content
+#[derive(Serialize)]
+pub struct CdnResponse<'response> {
+    /// human readable outcome of the request
+    pub msg: &'response str,
+    /// stable identifier of the error for failed requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+    /// files listed by /files, without their content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<File>>,
+    /// names of the files listed by /files?fields=name, in place of files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub names: Option<Vec<&'response str>>,
+    /// number of files available in total, if files only holds a page of them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+}
+
+/// ErrorCode identifies why a request failed, serialized in snake_case, e.g. "not_found"
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    NotFound,
+    MethodNotAllowed,
+    Conflict,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    Forbidden,
+    Internal,
+    ServiceUnavailable,
+    InsufficientStorage,
+}
+
+impl ErrorCode {
+    /// status is the http status responses carrying the code are sent with
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::RangeNotSatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::InsufficientStorage => StatusCode::INSUFFICIENT_STORAGE,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HealthResponse {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+struct StatsResponse {
+    /// number of files in the store
+    files: usize,
+    /// sum of the sizes of all files in the store
+    bytes: u64,
+    /// outcomes of the store lookups of downloads since the start
+    lookups: metrics::Lookups,
+}
+
+/// load_file reads entry into a File, dropping the content if it exceeds Config::cache_max_size
+async fn load_file(
+    config: &Config,
+    storage: &dyn StorageBackend,
+    entry: storage::Entry,
+) -> Option<File> {
+    let content = match storage.read(&entry.key).await {
+        Ok(content) => content,
+        Err(err) => {
+            warn!(file = %entry.key, "skipping unreadable file: {}", err);
+            return None;
+        }
+    };
+    let mut file = File::new(entry.key, content.into(), entry.modified);
+    if file.size > config.cache_max_size {
+        file.content = None;
+    }
+    Some(file)
+}
+
+/// init_store loads the files persisted in the store directory and its subdirectories, each keyed
+/// by its path relative to the store directory, e.g. "css/app.css", via Config::storage. In
+/// memory only mode the store starts out empty. With LocalDisk the store directory is created if
+/// missing and files left behind by uploads interrupted by a crash are removed
+pub async fn init_store(config: &Config) -> Result<FileStore> {
+    let mut files = HashMap::new();
+    if config.memory_only {
+        return Ok(Arc::new(RwLock::new(files)));
+    }
+    if config.storage.is_none() {
+        ensure_store_dir(&config.store_dir).await?;
+    }
+    let storage = config.storage();
+    storage
+        .recover()
+        .await
+        .context("Failed to create file store")?;
+    let entries = storage
+        .list()
+        .await
+        .context("Failed to list the files in the store")?;
+    for entry in entries {
+        if let Some(file) = load_file(config, storage.as_ref(), entry).await {
+            files.insert(file.name.clone(), file);
+        }
+    }
+    load_metadata(config, &mut files).await;
+    info!(
+        "Found {} File(s) on disk, loading into memory store",
+        files.len()
+    );
+    Ok(Arc::new(RwLock::new(files)))
+}
+
+/// ensure_store_dir creates the store directory if it is missing, failing with an explanation if
+/// the path is taken by something other than a directory
+async fn ensure_store_dir(dir: &Path) -> Result<()> {
+    match fs::metadata(dir).await {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => anyhow::bail!(
+            "Store path '{}' exists but is not a directory, remove it or point CDN_STORE_DIR elsewhere",
+            dir.display()
+        ),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Failed to create store directory '{}'", dir.display())),
+        Err(err) => Err(err)
+            .with_context(|| format!("Failed to access store directory '{}'", dir.display())),
+    }
+}
+
+/// watch_store rescans the store directory every interval, so files added, changed or removed on
+/// disk without going through the api, e.g. via rsync, are picked up. Files are considered
+/// changed if their size or modification time differ from the store
+async fn watch_store(db_handle: FileStore, config: Arc<Config>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        match rescan(&db_handle, &config).await {
+            Ok(rescan) if rescan.added + rescan.updated + rescan.removed > 0 => info!(
+                "Rescanned store, loaded {} new and {} changed File(s), dropped {} removed File(s)",
+                rescan.added, rescan.updated, rescan.removed
+            ),
+            Ok(_) => {}
+            Err(err) => warn!("Failed to rescan store: {:#}", err),
+        }
+    }
+}
+
+/// Rescan counts the files whose entries in the store were changed by rescan
+struct Rescan {
+    added: usize,
+    updated: usize,
+    removed: usize,
+}
+
+/// rescan brings the store in line with the files held by the backend, loading files that are new
+/// or changed and dropping files that are gone
+async fn rescan(db_handle: &FileStore, config: &Config) -> Result<Rescan> {
+    let storage = config.storage();
+    let entries = storage.list().await?;
+
+    let (changed, removed) = {
+        let store = read_store(db_handle);
+        let on_disk = entries
+            .iter()
+            .map(|entry| entry.key.as_str())
+            .collect::<std::collections::HashSet<&str>>();
+        let removed = store
+            .keys()
+            .filter(|key| !on_disk.contains(key.as_str()))
+            .cloned()
+            .collect::<Vec<String>>();
+        let changed = entries
+            .into_iter()
+            .filter(|entry| {
+                store
+                    .get(&entry.key)
+                    .is_none_or(|file| file.size != entry.size || file.modified != entry.modified)
+            })
+            .collect::<Vec<storage::Entry>>();
+        (changed, removed)
+    };
+
+    let mut rescan = Rescan {
+        added: 0,
+        updated: 0,
+        removed: 0,
+    };
+    for entry in changed {
+        if let Some(mut file) = load_file(config, storage.as_ref(), entry).await {
+            let mut store = write_store(db_handle);
+            // the expiry and content type belong to the name, not to the content
+            if let Some(old) = store.get(&file.name) {
+                file.expires = old.expires;
+                file.content_type = old.content_type.clone();
+            }
+            let name = file.name.clone();
+            match store.insert(name.clone(), file) {
+                Some(_) => {
+                    rescan.updated += 1;
+                    config.events.publish(Action::Updated, &name);
+                }
+                None => {
+                    rescan.added += 1;
+                    config.events.publish(Action::Uploaded, &name);
+                }
+            }
+        }
+    }
+    for key in removed {
+        // an upload may have finished since the scan, only drop files that are really gone
+        if storage.exists(&key).await.unwrap_or(true) {
+            continue;
+        }
+        write_store(db_handle).remove(&key);
+        rescan.removed += 1;
+        config.events.publish(Action::Deleted, &key);
+    }
+    Ok(rescan)
+}
+
+/// reload rescans the store on demand, e.g. after files were copied into the store directory,
+/// responding with how many files were added, updated and removed
+pub async fn reload(
+    db_handle: FileStore,
+    config: &Config,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    if config.memory_only {
+        return error_response(
+            ErrorCode::BadRequest,
+            "The store is kept in memory only, there is nothing to reload",
+        );
+    }
+    match rescan(&db_handle, config).await {
+        Ok(rescan) => response(
+            StatusCode::OK,
+            &format!(
+                "Reloaded store, added {} new, {} changed and {} removed File(s)",
+                rescan.added, rescan.updated, rescan.removed
+            ),
+        ),
+        Err(err) => error_response(
+            ErrorCode::Internal,
+            &format!("Failed to reload store: {:#}", err),
+        ),
+    }
+}
+
+/// ChannelBody streams the chunks sent by a producer task as the response body, the bounded
+/// channel keeps the producer from running ahead of the client
+struct ChannelBody(tokio::sync::mpsc::Receiver<std::io::Result<Bytes>>);
+
+impl Body for ChannelBody {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, std::io::Error>>> {
+        self.get_mut()
+            .0
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map(Frame::data)))
+    }
+}
+
+/// CatchUnwind resolves to the output of its future, or to the payload of a panic while polling it
+struct CatchUnwind<F>(Pin<Box<F>>);
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = std::thread::Result<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+        // a future that panicked is never polled again, so its broken state can't be observed
+        let polled =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.0.as_mut().poll(cx)));
+        match polled {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    }
+}
+
+fn full<T: Into<Bytes>>(chunk: T) -> http_body_util::combinators::BoxBody<Bytes, std::io::Error> {
+    Full::new(chunk.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+/// Format is the serialization of CdnResponse bodies, negotiated per request via Accept
+#[derive(Clone, Copy)]
+enum Format {
+    Json,
+    MessagePack,
+}
+
+tokio::task_local! {
+    /// format negotiated for the request being handled, set by response_handler
+    static FORMAT: Format;
+}
+
+/// serialize encodes value in the format negotiated for the current request, JSON outside of
+/// response_handler, returning the Content-Type alongside
+fn serialize<T: Serialize>(value: &T) -> Result<(&'static str, Vec<u8>)> {
+    Ok(
+        match FORMAT.try_with(|format| *format).unwrap_or(Format::Json) {
+            Format::Json => ("application/json", serde_json::to_vec(value)?),
+            Format::MessagePack => ("application/msgpack", msgpack::to_vec(value)?),
+        },
+    )
+}
+
+/// accepts reports whether the Accept header lists mime, parameters like q are ignored
+fn accepts(headers: &HeaderMap, mime: &str) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|accepted| accepted.split(';').next())
+        .any(|accepted| accepted.trim().eq_ignore_ascii_case(mime))
+}
+
+/// serialized_response responds with status and value serialized via serialize, every json
+/// body goes through here so failing to serialize one fails the request with 500
+fn serialized_response<T: Serialize>(
+    status: StatusCode,
+    value: &T,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let (content_type, body) = serialize(value)?;
+    Ok(Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, content_type)
+        .body(full(body))?)
+}
+
+/// response generates a Result<Response, ...> from the http statuscode and a message, containing
+///     { msg: msg }
+fn response(code: StatusCode, msg: &str) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    serialized_response(
+        code,
+        &CdnResponse {
+            msg,
+            code: None,
+            files: None,
+            names: None,
+            total: None,
+        },
+    )
+}
+
+/// error_response generates a failed Result<Response, ...> with the status of code, containing
+///     { msg: msg, code: code }
+/// clients should branch on code, msg is meant for humans and may change at any time
+fn error_response(code: ErrorCode, msg: &str) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    serialized_response(
+        code.status(),
+        &CdnResponse {
+            msg,
+            code: Some(code),
+            files: None,
+            names: None,
+            total: None,
+        },
+    )
+}
+
+/// ROUTES are the top level routes known to allowed_methods
+const ROUTES: &[&str] = &[
+    "", "health", "ready", "stats", "metrics", "events", "files", "file", "admin",
+];
+
+/// allowed_methods lists the methods supported by the top level route, None for unknown routes
+fn allowed_methods(route: &str) -> Option<&'static [Method]> {
+    const GET_ONLY: &[Method] = &[Method::GET];
+    const FILE: &[Method] = &[
+        Method::GET,
+        Method::HEAD,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+    ];
+    const FILES: &[Method] = &[Method::GET, Method::DELETE];
+    const POST_ONLY: &[Method] = &[Method::POST];
+    match route {
+        "" | "health" | "ready" | "stats" | "metrics" | "events" => Some(GET_ONLY),
+        "files" => Some(FILES),
+        "file" => Some(FILE),
+        "admin" => Some(POST_ONLY),
+        _ => None,
+    }
+}
+
+#[derive(Serialize)]
+struct NotFoundResponse {
+    msg: &'static str,
+    code: ErrorCode,
+    routes: Vec<RouteInfo>,
+}
+
+#[derive(Serialize)]
+struct RouteInfo {
+    path: String,
+    methods: Vec<&'static str>,
+}
+
+/// not_found responds to unknown paths, along with the known routes if Config::list_routes is set
+fn not_found(config: &Config) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    if !config.list_routes {
+        return error_response(ErrorCode::NotFound, "Not Found");
+    }
+    let routes = ROUTES
+        .iter()
+        .map(|route| RouteInfo {
+            path: format!("/{}", route),
+            methods: allowed_methods(route)
+                .unwrap_or_default()
+                .iter()
+                .map(Method::as_str)
+                .collect(),
+        })
+        .collect();
+    serialized_response(
+        StatusCode::NOT_FOUND,
+        &NotFoundResponse {
+            msg: "Not Found",
+            code: ErrorCode::NotFound,
+            routes,
+        },
+    )
+}
+
+fn join_methods(methods: &[Method]) -> String {
+    methods
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+/// method_not_allowed generates a 405 response for a known route, listing the methods the route
+/// supports in the Allow header
+fn method_not_allowed(route: &str) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let allow = join_methods(allowed_methods(route).unwrap_or_default());
+    let mut res = error_response(ErrorCode::MethodNotAllowed, "Method Not Allowed")?;
+    res.headers_mut()
+        .insert(ALLOW, HeaderValue::from_str(&allow)?);
+    Ok(res)
+}
+
+/// accepts_gzip reports whether the Accept-Encoding header lists gzip without disabling it via q=0
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut parts = encoding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let disabled = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !disabled
+        })
+}
+
+/// encode_body gzips body if the client accepts it and the body is between GZIP_MIN_SIZE and
+/// GZIP_MAX_SIZE bytes, Content-Length always reflects the size of the returned body. Compressing
+/// runs on the blocking pool so it doesn't stall the other connections served by the worker
+async fn encode_body(
+    headers: &HeaderMap,
+    builder: hyper::http::response::Builder,
+    body: Bytes,
+) -> Result<(hyper::http::response::Builder, Bytes)> {
+    let builder = builder.header(VARY, "accept-encoding");
+    if (GZIP_MIN_SIZE..=GZIP_MAX_SIZE).contains(&body.len()) && accepts_gzip(headers) {
+        let uncompressed = body.clone();
+        let compressed = tokio::task::spawn_blocking(move || gzip::compress(&uncompressed)).await?;
+        // already compressed formats, like images, tend to grow instead
+        if compressed.len() < body.len() {
+            return Ok((
+                builder
+                    .header(CONTENT_ENCODING, "gzip")
+                    .header(CONTENT_LENGTH, compressed.len()),
+                compressed.into(),
+            ));
+        }
+    }
+    Ok((builder.header(CONTENT_LENGTH, body.len()), body))
+}
+
+/// etag_matches reports whether the If-None-Match header value lists etag or is a wildcard, using
+/// the weak comparison defined for If-None-Match
+fn etag_matches(value: &str, etag: &str) -> bool {
+    value.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+/// if_match_satisfied reports whether the If-Match header value lists etag or is a wildcard, using
+/// the strong comparison defined for If-Match, so weak tags never match
+fn if_match_satisfied(value: &str, etag: &str) -> bool {
+    value
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate == etag)
+}
+
+/// MAX_RANGES bounds the ranges of a single request, every range adds a part to the response
+const MAX_RANGES: usize = 16;
+
+/// parse_ranges parses a Range header value such as "bytes=0-99,200-299" against content of len
+/// bytes. Ranges that are malformed or unsatisfiable are skipped, None is returned if none remain,
+/// the header doesn't address bytes or it holds more than MAX_RANGES ranges
+fn parse_ranges(value: &str, len: usize) -> Option<Vec<std::ops::Range<usize>>> {
+    let specs = value.trim().strip_prefix("bytes=")?.split(',');
+    if specs.clone().count() > MAX_RANGES {
+        return None;
+    }
+    let ranges = specs
+        .filter_map(|spec| parse_range(spec, len))
+        .collect::<Vec<_>>();
+    (!ranges.is_empty()).then_some(ranges)
+}
+
+/// parse_range parses a single range of the form "start-end", "start-" or "-suffix" against
+/// content of len bytes, returning None if it is malformed or unsatisfiable
+fn parse_range(spec: &str, len: usize) -> Option<std::ops::Range<usize>> {
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        // suffix range, the last n bytes of the content
+        let suffix: usize = end.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return None;
+        }
+        return Some(len.saturating_sub(suffix)..len);
+    }
+
+    let start: usize = start.parse().ok()?;
+    if start >= len {
+        return None;
+    }
+    let end = match end {
+        "" => len - 1,
+        end => end.parse::<usize>().ok()?.min(len - 1),
+    };
+    if end < start {
+        return None;
+    }
+    Some(start..end + 1)
+}
+
+/// content_type guesses the mime type of file_name by its extension, unknown extensions are served
+/// as application/octet-stream
+fn content_type(file_name: &str) -> &'static str {
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "wasm" => "application/wasm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+/// content_disposition builds the Content-Disposition header for file_name served as mime, names
+/// that can't be represented in a plain quoted string are sent RFC 5987 encoded via filename*
+/// with an ascii fallback for older clients
+fn content_disposition(file_name: &str, mime: &str, attachment: bool) -> String {
+    let mime = mime
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let viewable = ["text/", "image/", "video/", "audio/"]
+        .iter()
+        .any(|prefix| mime.starts_with(prefix))
+        || mime == "application/json"
+        || mime == "application/pdf";
+    let disposition = if attachment || !viewable {
+        "attachment"
+    } else {
+        "inline"
+    };
+
+    let plain = |c: char| (' '..='~').contains(&c) && c != '"' && c != '\\';
+    if file_name.chars().all(plain) {
+        return format!("{}; filename=\"{}\"", disposition, file_name);
+    }
+    let fallback = file_name
+        .chars()
+        .map(|c| if plain(c) { c } else { '_' })
+        .collect::<String>();
+    let encoded = file_name
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect::<String>();
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition, fallback, encoded
+    )
+}
+
+/// query_params parses the query string of uri into a map, later duplicates win
+fn query_params(uri: &hyper::Uri) -> HashMap<String, String> {
+    form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes())
+        .into_owned()
+        .collect()
+}
+
+/// usize_param parses the query parameter key, falling back to default if it is absent and
+/// returning None if it is malformed
+fn usize_param(params: &HashMap<String, String>, key: &str, default: usize) -> Option<usize> {
+    match params.get(key) {
+        Some(value) => value.parse().ok(),
+        None => Some(default),
+    }
+}
+
+/// preflight answers OPTIONS requests for a route supporting methods, listing them in Allow for
+/// plain requests and in Access-Control-Allow-Methods for CORS preflights. The allowed origin is
+/// attached by response_handler like for every other response
+fn preflight(
+    headers: &HeaderMap,
+    methods: &[Method],
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let allow_headers = headers
+        .get(ACCESS_CONTROL_REQUEST_HEADERS)
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::from_static(CORS_ALLOW_HEADERS));
+    let methods = join_methods(methods);
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(ALLOW, &methods)
+        .header(ACCESS_CONTROL_ALLOW_METHODS, methods)
+        .header(ACCESS_CONTROL_ALLOW_HEADERS, allow_headers)
+        .header(ACCESS_CONTROL_MAX_AGE, CORS_MAX_AGE)
+        .body(full(Bytes::new()))?)
+}
+
+/// cors sets Access-Control-Allow-Origin if origin is allowed by Config::cors_origins
+fn cors(config: &Config, origin: &HeaderValue, headers: &mut HeaderMap) {
+    if config.cors_origins.iter().any(|allowed| allowed == "*") {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    } else if config
+        .cors_origins
+        .iter()
+        .any(|allowed| allowed.as_bytes() == origin.as_bytes())
+    {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        headers.append(VARY, HeaderValue::from_static("origin"));
+    } else {
+        return;
+    }
+    headers.insert(
+        ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static(CORS_EXPOSE_HEADERS),
+    );
+}
+
+/// authorized checks the Authorization header against Config::basic_auth and Config::api_token,
+/// either of the configured schemes is accepted. Always true if neither is configured
+fn authorized(config: &Config, headers: &HeaderMap) -> bool {
+    if config.basic_auth.is_none() && config.api_token.is_none() {
+        return true;
+    }
+    let Some((scheme, credentials)) = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+    else {
+        return false;
+    };
+    let expected = if scheme.eq_ignore_ascii_case("basic") {
+        &config.basic_auth
+    } else if scheme.eq_ignore_ascii_case("bearer") {
+        &config.api_token
+    } else {
+        return false;
+    };
+    expected.as_ref().is_some_and(|expected| {
+        constant_time_eq(credentials.trim().as_bytes(), expected.as_bytes())
+    })
+}
+
+/// constant_time_eq compares a and b without short circuiting on the first differing byte, so
+/// the time taken doesn't leak how much of a secret was guessed correctly
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// unauthorized generates a 401 response challenging the client for each configured scheme
+fn unauthorized(config: &Config) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let mut res = error_response(ErrorCode::Unauthorized, "Unauthorized")?;
+    if config.basic_auth.is_some() {
+        res.headers_mut().append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("Basic realm=\"cdn\", charset=\"UTF-8\""),
+        );
+    }
+    if config.api_token.is_some() {
+        res.headers_mut().append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("Bearer realm=\"cdn\""),
+        );
+    }
+    Ok(res)
+}
+
+/// signed_url returns the path and query of a download of name that is valid until expires, for
+/// a cdn configured with secret as Config::signing_secret. Returns None for names that can't be
+/// stored. The segments of the name are percent encoded, the signature covers the decoded name
+pub fn signed_url(secret: &str, name: &str, expires: SystemTime) -> Option<String> {
+    let key = store_key(name)?;
+    let exp = expires
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let sig = sha256::hex(&sha256::hmac(
+        secret.as_bytes(),
+        format!("{}\n{}", key, exp).as_bytes(),
+    ));
+    Some(format!(
+        "/file/{}?exp={}&sig={}",
+        encode_key(&key),
+        exp,
+        sig
+    ))
+}
+
+/// verify_signature checks that the exp and sig parameters of a download of file_name were minted
+/// via signed_url with secret and haven't expired yet
+fn verify_signature(
+    secret: &str,
+    file_name: &str,
+    params: &HashMap<String, String>,
+) -> Result<(), &'static str> {
+    let (Some(exp), Some(sig)) = (params.get("exp"), params.get("sig")) else {
+        return Err("Downloads require a signed url");
+    };
+    let Some(key) = store_key(file_name) else {
+        return Err("Invalid signature");
+    };
+    let expected = sha256::hex(&sha256::hmac(
+        secret.as_bytes(),
+        format!("{}\n{}", key, exp).as_bytes(),
+    ));
+    // compared in constant time, so the signature can't be guessed byte by byte
+    if !constant_time_eq(expected.as_bytes(), sig.as_bytes()) {
+        return Err("Invalid signature");
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    match exp.parse::<u64>() {
+        Ok(exp) if exp >= now => Ok(()),
+        _ => Err("Signed url has expired"),
+    }
+}
+
+/// base64 encodes data using the standard alphabet with padding
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// PATH_SEGMENT is what is percent encoded in a path segment of urls pointing at files, anything
+/// but the unreserved characters of RFC 3986
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// decode_key percent decodes the segments of a request path into a key, e.g. ["my%20dir",
+/// "a.txt"] into "my dir/a.txt". Returns None for segments that aren't utf8 once decoded or that
+/// decode to a "/" or NUL, which would address a different key than the one requested
+fn decode_key(segments: &[&str]) -> Option<String> {
+    let segments = segments
+        .iter()
+        .map(|segment| percent_decode_str(segment).decode_utf8().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if segments.iter().any(|segment| segment.contains(['/', '\0'])) {
+        return None;
+    }
+    Some(segments.join("/"))
+}
+
+/// encode_key percent encodes every segment of key for use in the path of a url, the inverse of
+/// decode_key
+pub(crate) fn encode_key(key: &str) -> String {
+    key.split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// store_key normalizes file_name into the key of a file relative to the store directory, e.g.
+/// "/css//app.css" into "css/app.css". Returns None for names that could escape the store
+/// directory via "." or ".." components, empty names and names reserved for internal files
+fn store_key(file_name: &str) -> Option<String> {
+    let segments = file_name
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<&str>>();
+    let valid = |segment: &&str| {
+        *segment != "."
+            && *segment != ".."
+            && !segment.contains('\\')
+            && !segment.starts_with(RESERVED_PREFIX)
+    };
+    if segments.is_empty() || !segments.iter().all(valid) {
+        return None;
+    }
+    Some(segments.join("/"))
+}
+
+/// response_handler routes req and attaches the CORS headers configured for its origin. Bodies
+/// built from CdnResponse are sent as MessagePack to clients accepting application/msgpack.
+/// Handlers failing or panicking are answered with 500
+pub async fn response_handler(
+    req: Request<hyper::body::Incoming>,
+    db_handle: FileStore,
+    ready: Arc<AtomicBool>,
+    config: Arc<Config>,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let origin = req.headers().get(ORIGIN).cloned();
+    let format = match accepts(req.headers(), "application/msgpack")
+        || accepts(req.headers(), "application/x-msgpack")
+    {
+        true => Format::MessagePack,
+        false => Format::Json,
+    };
+    let routed = FORMAT.scope(format, route(req, db_handle, ready, Arc::clone(&config)));
+    // failing handlers fail their request, the connection is only ever dropped by hyper for
+    // errors on the connection itself
+    let mut res = match CatchUnwind(Box::pin(routed)).await {
+        Ok(Ok(res)) => res,
+        Ok(Err(err)) => {
+            error!("Failed to handle request: {:#}", err);
+            internal_error(format)?
+        }
+        Err(panic) => {
+            let cause = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown cause");
+            error!("Request handler panicked: {}", cause);
+            internal_error(format)?
+        }
+    };
+    if let Some(origin) = origin {
+        cors(&config, &origin, res.headers_mut());
+    }
+    if let Some(name) = &config.server_name {
+        res.headers_mut().insert(SERVER, name.clone());
+    }
+    Ok(res)
+}
+
+fn internal_error(format: Format) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    FORMAT.sync_scope(format, || {
+        error_response(ErrorCode::Internal, "Internal Server Error")
+    })
+}
+
+async fn route(
+    req: Request<hyper::body::Incoming>,
+    db_handle: FileStore,
+    ready: Arc<AtomicBool>,
+    config: Arc<Config>,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let path = req
+        .uri()
+        .path()
+        .split("/")
+        .filter(|e| !e.is_empty())
+        .collect::<Vec<&str>>();
+    // empty segments are dropped, so "/files/" and "//files" route like "/files", while "/" has
+    // no segment at all and routes to the index
+    let route = path.first().copied().unwrap_or_default();
+
+    if req.method() == Method::OPTIONS {
+        // a bare /file only accepts form uploads, everything else needs a file name
+        if route == "file" && path.len() == 1 {
+            return preflight(req.headers(), &[Method::POST, Method::PUT]);
+        }
+        if let Some(methods) = allowed_methods(route) {
+            return preflight(req.headers(), methods);
+        }
+    }
+
+    // probes stay public and answer while the store is loading, anything else would only see the
+    // store partially loaded
+    let probe = route == "health" || route == "ready";
+    if !probe && !ready.load(Ordering::Acquire) {
+        return error_response(ErrorCode::ServiceUnavailable, "Store is still loading");
+    }
+
+    let mutating = matches!(*req.method(), Method::POST | Method::PUT | Method::DELETE);
+    if (mutating || config.auth_reads)
+        && !probe
+        && allowed_methods(route).is_some()
+        && !authorized(&config, req.headers())
+    {
+        return unauthorized(&config);
+    }
+
+    // everything after /file/ makes up the key, which may address a nested file
+    let key = match (path.len() > 1).then(|| decode_key(&path[1..])) {
+        None => None,
+        Some(Some(key)) => Some(key),
+        Some(None) => {
+            return error_response(
+                ErrorCode::BadRequest,
+                "Malformed path, segments must decode to utf8 without '/' or NUL",
+            )
+        }
+    };
+
+    match route {
+        "" => match *req.method() {
+            Method::GET => index(db_handle),
+            _ => method_not_allowed(route),
+        },
+        "health" => match *req.method() {
+            Method::GET => health(),
+            _ => method_not_allowed(route),
+        },
+        "ready" => match *req.method() {
+            Method::GET => readiness(&ready),
+            _ => method_not_allowed(route),
+        },
+        "stats" => match *req.method() {
+            Method::GET => stats(db_handle),
+            _ => method_not_allowed(route),
+        },
+        "events" => match *req.method() {
+            Method::GET => event_stream(&config),
+            _ => method_not_allowed(route),
+        },
+        "metrics" => match *req.method() {
+            Method::GET => metrics(db_handle),
+            _ => method_not_allowed(route),
+        },
+        "admin" => match (req.method(), path.get(1)) {
+            (&Method::POST, Some(&"reload")) if path.len() == 2 => reload(db_handle, &config).await,
+            (&Method::POST, _) => not_found(&config),
+            _ => method_not_allowed(route),
+        },
+        "files" => match (req.method(), path.get(1)) {
+            (&Method::GET, None) => all(db_handle, &req).await,
+            (&Method::GET, Some(&"archive")) => archive(db_handle, &config),
+            (&Method::GET, Some(&"popular")) => popular(db_handle, &req).await,
+            (&Method::DELETE, None) => clear(db_handle, &config).await,
+            (&Method::GET | &Method::DELETE, Some(_)) => {
+                error_response(ErrorCode::NotFound, "Not Found")
+            }
+            _ => method_not_allowed(route),
+        },
+        "file" => match *req.method() {
+            // /file/:name/rename?to=<name>, also without to if name exists so forgetting it
+            // fails instead of uploading a file named rename. Any other POST is an upload
+            Method::POST
+                if key
+                    .as_ref()
+                    .and_then(|key| key.strip_suffix("/rename"))
+                    .is_some_and(|name| {
+                        query_params(req.uri()).contains_key("to")
+                            || read_store(&db_handle).contains_key(name)
+                    }) =>
+            {
+                let key = key.unwrap_or_default();
+                let name = key.strip_suffix("/rename").unwrap_or(&key);
+                let Some(to) = query_params(req.uri()).remove("to") else {
+                    return error_response(
+                        ErrorCode::BadRequest,
+                        &format!("Failed to rename file '{}': missing ?to=<name>", name),
+                    );
+                };
+                rename(db_handle, &config, name, &to).await
+            }
+            Method::POST | Method::PUT => {
+                let mode = match *req.method() {
+                    Method::PUT => UploadMode::Update,
+                    _ if query_params(req.uri())
+                        .get("overwrite")
+                        .is_some_and(|value| value == "true") =>
+                    {
+                        UploadMode::Overwrite
+                    }
+                    _ => UploadMode::Create,
+                };
+                upload(req, db_handle, &config, key, mode).await
+            }
+            Method::GET | Method::HEAD => {
+                let Some(key) = key else {
+                    return error_response(ErrorCode::NotFound, "No file path");
+                };
+                let params = query_params(req.uri());
+                // checksums and existence reveal as much about a file as downloading it, so they
+                // require a signature for the name of the file as well
+                let unsigned = |name: &str| {
+                    let secret = config.signing_secret.as_ref()?;
+                    verify_signature(secret, name, &params).err()
+                };
+                // /file/:name/checksum, unless a file of that name exists
+                if let Some(name) = key.strip_suffix("/checksum") {
+                    if req.method() == Method::GET && !read_store(&db_handle).contains_key(&key) {
+                        if let Some(reason) = unsigned(name) {
+                            return error_response(ErrorCode::Forbidden, reason);
+                        }
+                        return checksum(db_handle, &config, name);
+                    }
+                }
+                // /file/:name/exists, likewise
+                if let Some(name) = key.strip_suffix("/exists") {
+                    if req.method() == Method::GET && !read_store(&db_handle).contains_key(&key) {
+                        if let Some(reason) = unsigned(name) {
+                            return error_response(ErrorCode::Forbidden, reason);
+                        }
+                        return exists(&db_handle, &config, name);
+                    }
+                }
+                if let Some(reason) = unsigned(&key) {
+                    return error_response(ErrorCode::Forbidden, reason);
+                }
+                let head_only = req.method() == Method::HEAD;
+                match params.get("encoding").map(String::as_str) {
+                    None => {}
+                    Some("base64") => return download_base64(db_handle, &config, &key).await,
+                    Some(encoding) => {
+                        return error_response(
+                            ErrorCode::BadRequest,
+                            &format!("Unknown encoding '{}', expected base64", encoding),
+                        )
+                    }
+                }
+                let attachment = params.get("download").is_some_and(|value| value == "true");
+                download(
+                    db_handle,
+                    &config,
+                    req.headers(),
+                    &key,
+                    head_only,
+                    attachment,
+                )
+                .await
+            }
+            Method::DELETE => {
+                let Some(key) = key else {
+                    return error_response(ErrorCode::NotFound, "No file path");
+                };
+                delete(db_handle, &config, &key).await
+            }
+            _ => method_not_allowed(route),
+        },
+        _ => not_found(&config),
+    }
+}
+
+/// index renders a minimal html page linking every file in the store, for browsing the cdn by
+/// hand. /files stays the machine readable listing
+pub fn index(db_handle: FileStore) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let now = SystemTime::now();
+    let mut rows = String::new();
+    {
+        let handle = read_store(&db_handle);
+        let mut files = handle
+            .values()
+            .filter(|file| !file.expired(now))
+            .collect::<Vec<&File>>();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        for file in files {
+            // percent encoded names only consist of characters that need no html escaping
+            rows.push_str(&format!(
+                "<tr><td><a href=\"/file/{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                encode_key(&file.name),
+                html_escape(&file.name),
+                file.size,
+                HttpDate::from(file.modified)
+            ));
+        }
+    }
+    let page = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>rust_cdn</title></head>\n\
+         <body>\n<h1>Files</h1>\n<table>\n\
+         <tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n{}</table>\n</body>\n</html>\n",
+        rows
+    );
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(full(page))?)
+}
+
+/// html_escape escapes text for use in html content and quoted attributes
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// health answers liveness checks, it intentionally doesn't touch the store so it stays
+/// responsive while the store lock is contended
+pub fn health() -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    serialized_response(StatusCode::OK, &HealthResponse { status: "ok" })
+}
+
+/// readiness answers readiness checks, failing with 503 until the store is loaded
+pub fn readiness(ready: &AtomicBool) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    if !ready.load(Ordering::Acquire) {
+        return error_response(ErrorCode::ServiceUnavailable, "Store is still loading");
+    }
+    serialized_response(StatusCode::OK, &HealthResponse { status: "ok" })
+}
+
+/// stats reports how many files and bytes the store currently holds
+pub fn stats(db_handle: FileStore) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let stats = {
+        let lock = read_store(&db_handle);
+        StatsResponse {
+            files: lock.len(),
+            bytes: lock.values().map(|file| file.size).sum(),
+            lookups: metrics::lookups(),
+        }
+    };
+    serialized_response(StatusCode::OK, &stats)
+}
+
+/// metrics exposes request, upload and download counters along with the size of the store in the
+/// Prometheus text format
+pub fn metrics(db_handle: FileStore) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let (files, bytes) = {
+        let lock = read_store(&db_handle);
+        (lock.len(), lock.values().map(|file| file.size).sum())
+    };
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, metrics::CONTENT_TYPE)
+        .body(full(metrics::render(files, bytes)))?)
+}
+
+/// event_stream streams changes to the store as server-sent events until the client goes away,
+/// each naming the action and carrying it along with the file name as json, e.g.
+///     event: uploaded
+///     data: {"action":"uploaded","name":"css/app.css"}
+pub fn event_stream(config: &Config) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    // comments keep idle connections from being closed by Config::idle_timeout and proxies,
+    // they are sent well within the idle timeout if it is shorter than usual
+    const KEEP_ALIVE: Duration = Duration::from_secs(15);
+    let interval = config
+        .idle_timeout
+        .map_or(KEEP_ALIVE, |timeout| KEEP_ALIVE.min(timeout / 2))
+        .max(Duration::from_millis(1));
+    let mut events = config.events.subscribe();
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut keep_alive = tokio::time::interval(interval);
+        loop {
+            let message = tokio::select! {
+                event = events.recv() => match event {
+                    Ok(Some(event)) => format!(
+                        "event: {}\ndata: {}\n\n",
+                        event.action.as_str(),
+                        serde_json::to_string(&event).unwrap_or_default()
+                    ),
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                        format!(": missed {} events\n\n", missed)
+                    }
+                    Ok(None) | Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+                // the first tick completes at once, which makes clients see the stream is open
+                _ = keep_alive.tick() => String::from(":\n\n"),
+                _ = tx.closed() => break,
+            };
+            if tx.send(Ok(Bytes::from(message))).await.is_err() {
+                break;
+            }
+        }
+    });
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(ChannelBody(rx).boxed())?)
+}
+
+/// upload stores a file, either from a form-urlencoded or json body holding name and content, or,
+/// if path_name is given via /file/:name, the raw request body is stored verbatim. Form and json
+/// bodies may use filename and file instead of name and content. Names may contain directories,
+/// e.g. css/app.css, which are created as needed. Whether the name may or must already exist
+/// depends on mode, with ?ttl=<seconds> the file expires after that long. With an If-Match header
+/// the file is only replaced if it exists with a matching etag. The file is served with the
+/// content type given via ?content_type=, the request's Content-Type for raw bodies or
+/// content_type in form and json bodies, falling back to guessing from the extension. With
+/// ?dry_run=true the upload is validated and its body read as usual without storing anything,
+/// answering with 200 if it would have succeeded. Bodies sent with Content-Encoding: gzip are
+/// decompressed before anything else, other encodings are rejected with 415
+pub async fn upload(
+    req: Request<hyper::body::Incoming>,
+    db_handle: FileStore,
+    config: &Arc<Config>,
+    path_name: Option<String>,
+    mode: UploadMode,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let gzipped = match req.headers().get(CONTENT_ENCODING) {
+        None => false,
+        Some(value) => match value.to_str().map(str::trim) {
+            Ok(encoding) if encoding.eq_ignore_ascii_case("identity") => false,
+            Ok(encoding) if encoding.eq_ignore_ascii_case("gzip") => true,
+            _ => {
+                return error_response(
+                    ErrorCode::UnsupportedMediaType,
+                    "Unsupported Content-Encoding, expected gzip or identity",
+                )
+            }
+        },
+    };
+    if !gzipped {
+        return store_upload(req, db_handle, config, path_name, mode).await;
+    }
+
+    // the compressed body is bounded by max_body_size just like what it decompresses to, which
+    // is then stored as if it had been sent uncompressed
+    let (mut parts, body) = req.into_parts();
+    let limit = usize::try_from(config.max_body_size).unwrap_or(usize::MAX);
+    let compressed = match Limited::new(body, limit).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(err) if err.is::<LengthLimitError>() => return payload_too_large(config),
+        Err(err) => {
+            return error_response(
+                ErrorCode::BadRequest,
+                &format!("Failed to read request body: {}", err),
+            )
+        }
+    };
+    // inflating is cpu bound, so it runs on the blocking pool instead of stalling this worker
+    let decompressed = tokio::task::spawn_blocking(move || gzip::decompress(&compressed, limit));
+    let content = match decompressed.await? {
+        Ok(content) => content,
+        Err(gzip::DecompressError::TooLarge) => return payload_too_large(config),
+        Err(err) => {
+            return error_response(
+                ErrorCode::BadRequest,
+                &format!("Failed to decompress request body: {}", err),
+            )
+        }
+    };
+    parts.headers.remove(CONTENT_ENCODING);
+    parts
+        .headers
+        .insert(CONTENT_LENGTH, HeaderValue::from(content.len()));
+    let req = Request::from_parts(parts, Full::new(Bytes::from(content)));
+    store_upload(req, db_handle, config, path_name, mode).await
+}
+
+/// store_upload is upload once the body is decoded
+async fn store_upload<B>(
+    req: Request<B>,
+    db_handle: FileStore,
+    config: &Arc<Config>,
+    path_name: Option<String>,
+    mode: UploadMode,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>>
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    // reject bodies announcing their size upfront before reading anything, bodies without a
+    // Content-Length are counted while they stream in
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if content_length.is_some_and(|len| len > config.max_body_size) {
+        return payload_too_large(config);
+    }
+    // raw bodies are the file itself
+    if let Some(name) = &path_name {
+        if content_length.is_some_and(|len| len > config.upload_limit()) {
+            return file_too_large(config, name);
+        }
+    }
+
+    let if_match = match req.headers().get(IF_MATCH).map(|value| value.to_str()) {
+        None => None,
+        Some(Ok(value)) => Some(value.to_string()),
+        Some(Err(_)) => return error_response(ErrorCode::BadRequest, "Malformed If-Match header"),
+    };
+
+    let content_type = match query_params(req.uri()).remove("content_type") {
+        None => None,
+        Some(content_type) if valid_content_type(&content_type) => Some(content_type),
+        Some(content_type) => return invalid_content_type(&content_type),
+    };
+
+    let ttl = match query_params(req.uri()).get("ttl") {
+        None => None,
+        Some(ttl) => match ttl.parse::<u64>() {
+            Ok(secs) if secs > 0 => Some(Duration::from_secs(secs)),
+            _ => {
+                return error_response(
+                    ErrorCode::BadRequest,
+                    "Invalid ttl, expected a positive number of seconds",
+                )
+            }
+        },
+    };
+
+    let dry_run = query_params(req.uri())
+        .get("dry_run")
+        .is_some_and(|value| value == "true");
+
+    if let Some(boundary) = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(multipart::boundary)
+    {
+        if path_name.is_none() {
+            let options = UploadOptions {
+                mode,
+                if_match: if_match.as_deref(),
+                ttl,
+                dry_run,
+            };
+            return upload_multipart(req, db_handle, config, &boundary, &options).await;
+        }
+    }
+
+    let (filename, persisted, content_type) = match path_name {
+        // raw uploads know their name upfront, so the body is streamed to disk as it arrives
+        Some(name) => {
+            let filename = match upload_name(&db_handle, config, &name, mode, if_match.as_deref()) {
+                Ok(filename) => filename,
+                Err((code, msg)) => return error_response(code, &msg),
+            };
+            // form encodings are what clients such as curl send by default, not the file's type
+            let content_type = content_type.or_else(|| {
+                req.headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .filter(|value| {
+                        let mime = value.split(';').next().unwrap_or_default().trim();
+                        !mime.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+                            && !mime.eq_ignore_ascii_case("multipart/form-data")
+                    })
+                    .filter(|value| valid_content_type(value))
+                    .map(String::from)
+            });
+            let body = req.into_body();
+            let persisted = persist(config, filename.clone(), body, content_length, dry_run).await;
+            (filename, persisted, content_type)
+        }
+        None => {
+            let is_json = req
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(';').next())
+                .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+            let limit = usize::try_from(config.max_body_size).unwrap_or(usize::MAX);
+            let whole_body = match Limited::new(req.into_body(), limit).collect().await {
+                Ok(body) => body.to_bytes(),
+                Err(err) if err.is::<LengthLimitError>() => return payload_too_large(config),
+                Err(err) => {
+                    return error_response(
+                        ErrorCode::BadRequest,
+                        &format!("Failed to read request body: {}", err),
+                    )
+                }
+            };
+            if let Some(expected) = content_length {
+                let err = LengthMismatch {
+                    expected,
+                    received: whole_body.len() as u64,
+                };
+                if err.expected != err.received {
+                    return error_response(ErrorCode::BadRequest, &err.to_string());
+                }
+            }
+
+            let upload = if is_json {
+                match serde_json::from_slice::<JsonUploadRequest>(&whole_body) {
+                    Ok(upload) => UploadRequest {
+                        name: upload.name,
+                        content: upload.content.into_bytes(),
+                        content_type: upload.content_type,
+                    },
+                    Err(err) => {
+                        return error_response(
+                            ErrorCode::BadRequest,
+                            &format!("Malformed JSON request body: {}", err),
+                        )
+                    }
+                }
+            } else {
+                let mut params = form_urlencoded::parse(whole_body.as_ref())
+                    .into_owned()
+                    .collect::<HashMap<String, String>>();
+
+                let mut field =
+                    |names: [&str; 2]| names.iter().find_map(|name| params.remove(*name));
+                let (Some(name), Some(content)) = (field(NAME_FIELDS), field(CONTENT_FIELDS))
+                else {
+                    return error_response(
+                        ErrorCode::BadRequest,
+                        "Missing name or content in request body",
+                    );
+                };
+                UploadRequest {
+                    name,
+                    content: content.into_bytes(),
+                    content_type: params.remove("content_type"),
+                }
+            };
+            let content_type = match content_type.or(upload.content_type) {
+                Some(content_type) if !valid_content_type(&content_type) => {
+                    return invalid_content_type(&content_type)
+                }
+                content_type => content_type,
+            };
+
+            let filename =
+                match upload_name(&db_handle, config, &upload.name, mode, if_match.as_deref()) {
+                    Ok(filename) => filename,
+                    Err((code, msg)) => return error_response(code, &msg),
+                };
+            let body = Full::new(Bytes::from(upload.content));
+            let persisted = persist(config, filename.clone(), body, None, dry_run).await;
+            (filename, persisted, content_type)
+        }
+    };
+
+    let writer = match persisted {
+        Ok(writer) => writer,
+        Err(err) => return persist_error(config, &filename, err),
+    };
+    if dry_run {
+        return response(
+            StatusCode::OK,
+            &format!("File '{}' would have been stored", filename),
+        );
+    }
+    if let Err(err) = publish(
+        &db_handle,
+        config,
+        writer,
+        content_type,
+        mode,
+        if_match.as_deref(),
+        ttl,
+    )
+    .await
+    {
+        return persist_error(config, &filename, err);
+    }
+
+    match mode {
+        UploadMode::Update => response(StatusCode::OK, &format!("Updated file '{}'", filename)),
+        _ => response(StatusCode::CREATED, &format!("Stored file '{}'", filename)),
+    }
+}
+
+/// upload_multipart stores every part of a multipart/form-data body carrying a filename as a
+/// separate file, each streamed to disk as it arrives and served with the Content-Type of its
+/// part. Parts stored before a failing part are kept
+async fn upload_multipart<B>(
+    req: Request<B>,
+    db_handle: FileStore,
+    config: &Arc<Config>,
+    boundary: &str,
+    options: &UploadOptions<'_>,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>>
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut parts = multipart::Multipart::new(req.into_body(), boundary, config.max_body_size);
+    let mut stored = Vec::new();
+    loop {
+        let part = match parts.next_part().await {
+            Ok(Some(part)) => part,
+            Ok(None) => break,
+            Err(err) if err.is::<PayloadTooLarge>() => return payload_too_large(config),
+            Err(err) => {
+                return error_response(
+                    ErrorCode::BadRequest,
+                    &format!("Failed to read multipart body: {}", err),
+                )
+            }
+        };
+        // plain form fields carry no file
+        let Some(name) = part.filename else {
+            continue;
+        };
+        // browsers send octet-stream for any type they don't know, guessing does better
+        let content_type = part
+            .content_type
+            .filter(|content_type| valid_content_type(content_type))
+            .filter(|content_type| !content_type.eq_ignore_ascii_case("application/octet-stream"));
+        let (mode, if_match) = (options.mode, options.if_match);
+        let filename = match upload_name(&db_handle, config, &name, mode, if_match) {
+            Ok(filename) => filename,
+            Err((code, msg)) => return error_response(code, &msg),
+        };
+
+        let persisted = async {
+            let mut writer = FileWriter::create(config, filename.clone(), options.dry_run).await?;
+            while let Some(chunk) = parts.next_chunk().await? {
+                writer.write(&chunk).await?;
+            }
+            match options.dry_run {
+                true => writer.finish().await.map(|mut file| {
+                    file.content_type = content_type;
+                    file
+                }),
+                false => {
+                    let ttl = options.ttl;
+                    publish(
+                        &db_handle,
+                        config,
+                        writer,
+                        content_type,
+                        mode,
+                        if_match,
+                        ttl,
+                    )
+                    .await
+                }
+            }
+        };
+        match persisted.await {
+            Ok(file) => stored.push(file),
+            Err(err) => return persist_error(config, &filename, err),
+        }
+    }
+
+    if stored.is_empty() {
+        return error_response(ErrorCode::BadRequest, "No files in multipart body");
+    }
+    let (status, msg) = match (options.dry_run, stored.len()) {
+        (true, 1) => (
+            StatusCode::OK,
+            String::from("1 file would have been stored"),
+        ),
+        (true, n) => (
+            StatusCode::OK,
+            format!("{} files would have been stored", n),
+        ),
+        (false, 1) => (StatusCode::CREATED, String::from("Stored 1 file")),
+        (false, n) => (StatusCode::CREATED, format!("Stored {} files", n)),
+    };
+    serialized_response(
+        status,
+        &CdnResponse {
+            msg: &msg,
+            code: None,
+            total: None,
+            files: Some(stored),
+            names: None,
+        },
+    )
+}
+
+/// publish finishes the file written by writer and makes it available to readers, replacing any
+/// previous file of the same name, and returns its metadata. With a ttl the file expires that
+/// long from now.
+///
+/// The store is checked against mode and if_match once more right before the file replaces
+/// anything, as it may have changed while the upload streamed in. Uploads no longer fitting fail with
+/// Rejected, discarding what was written and leaving both the storage and the store untouched.
+/// Publishing runs on a task of its own, so a client going away once the file is finished can't
+/// leave it in storage without being in the store
+async fn publish(
+    db_handle: &FileStore,
+    config: &Arc<Config>,
+    writer: FileWriter,
+    content_type: Option<String>,
+    mode: UploadMode,
+    if_match: Option<&str>,
+    ttl: Option<Duration>,
+) -> Result<File> {
+    let (db_handle, config) = (Arc::clone(db_handle), Arc::clone(config));
+    let if_match = if_match.map(String::from);
+    let published = tokio::spawn(async move {
+        let if_match = if_match.as_deref();
+        publish_file(
+            &db_handle,
+            &config,
+            writer,
+            content_type,
+            mode,
+            if_match,
+            ttl,
+        )
+        .await
+    });
+    published.await?
+}
+
+/// PUBLISHING is held while files are checked against and published to the store, so no other
+/// upload, rename or delete can change the name in between
+static PUBLISHING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+async fn publish_file(
+    db_handle: &FileStore,
+    config: &Config,
+    writer: FileWriter,
+    content_type: Option<String>,
+    mode: UploadMode,
+    if_match: Option<&str>,
+    ttl: Option<Duration>,
+) -> Result<File> {
+    let _publishing = PUBLISHING.lock().await;
+    let conflict = upload_conflict(&read_store(db_handle), config, &writer.name, mode, if_match);
+    if let Err((code, msg)) = conflict {
+        // the writer is dropped unfinished, which discards what was written
+        return Err(Rejected { code, msg }.into());
+    }
+    let mut file = writer.finish().await?;
+    file.content_type = content_type;
+    if config.dedup {
+        dedup(db_handle, config, &mut file).await;
+    }
+    file.expires = ttl.map(|ttl| SystemTime::now() + ttl);
+    let metadata = file.metadata();
+    metrics::record_upload();
+    // choosing what to evict and inserting under one lock keeps the store within its limit even
+    // while other files are accessed or expire in between
+    let (evicted, replaced) = {
+        let mut store = write_store(db_handle);
+        let evicted = evict(&mut store, config, &file);
+        (evicted, store.insert(file.name.clone(), file))
+    };
+    let evicted_metadata = remove_evicted(config, evicted).await;
+    let action = match replaced {
+        Some(_) => Action::Updated,
+        None => Action::Uploaded,
+    };
+    // only notify once the file is actually servable
+    if let Some(url) = &config.webhook_url {
+        webhook::notify(
+            url.clone(),
+            &webhook::Upload {
+                name: &metadata.name,
+                size: metadata.size,
+                sha256: &metadata.sha256,
+            },
+        );
+    }
+    config.events.publish(action, &metadata.name);
+    if evicted_metadata
+        || metadata.has_metadata()
+        || replaced.is_some_and(|replaced| replaced.has_metadata())
+    {
+        save_metadata(db_handle, config).await;
+    }
+    Ok(metadata)
+}
+
+/// evict removes the least recently accessed files from store until file fits into
+/// Config::max_store_size, in place of a previous file of the same name, returning them
+fn evict(store: &mut HashMap<String, File>, config: &Config, file: &File) -> Vec<File> {
+    let Some(limit) = config.max_store_size else {
+        return Vec::new();
+    };
+    let mut evicted = Vec::new();
+    loop {
+        let others = || store.values().filter(|other| other.name != file.name);
+        if others().map(|other| other.size).sum::<u64>() + file.size <= limit {
+            break;
+        }
+        let Some(victim) = others()
+            .min_by_key(|other| (other.accessed.load(Ordering::Relaxed), other.name.clone()))
+            .map(|other| other.name.clone())
+        else {
+            break;
+        };
+        evicted.extend(store.remove(&victim));
+    }
+    evicted
+}
+
+/// remove_evicted deletes the files evict took out of the store from Config::storage. Returns
+/// whether an evicted file had metadata
+async fn remove_evicted(config: &Config, evicted: Vec<File>) -> bool {
+    let mut evicted_metadata = false;
+    for removed in evicted {
+        if !config.memory_only {
+            match config.storage().delete(&removed.name).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => warn!(file = %removed.name, "failed to evict file: {}", err),
+            }
+        }
+        info!(file = %removed.name, size = removed.size, "evicted least recently used file");
+        config.events.publish(Action::Deleted, &removed.name);
+        evicted_metadata |= removed.has_metadata();
+    }
+    evicted_metadata
+}
+
+/// save_metadata persists the expiries and content types of all files in the store to
+/// EXPIRIES_FILE and CONTENT_TYPES_FILE of Config::storage, so they survive restarts. Failures are only logged, the
+/// files themselves are stored just fine
+async fn save_metadata(db_handle: &FileStore, config: &Config) {
+    if config.memory_only {
+        return;
+    }
+    // concurrent saves could otherwise finish out of order, overwriting newer metadata
+    static SAVING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+    let _saving = SAVING.lock().await;
+    let (expiries, content_types) = {
+        let store = read_store(db_handle);
+        let expiries = store
+            .values()
+            .filter_map(|file| {
+                let expires = file.expires?.duration_since(UNIX_EPOCH).ok()?;
+                Some((file.name.clone(), expires.as_secs()))
+            })
+            .collect::<HashMap<String, u64>>();
+        let content_types = store
+            .values()
+            .filter_map(|file| Some((file.name.clone(), file.content_type.clone()?)))
+            .collect::<HashMap<String, String>>();
+        (expiries, content_types)
+    };
+    save_sidecar(config, EXPIRIES_FILE, &expiries, "file expiries").await;
+    save_sidecar(config, CONTENT_TYPES_FILE, &content_types, "content types").await;
+}
+
+/// save_sidecar atomically replaces the internal document name of Config::storage with value
+async fn save_sidecar<T: Serialize>(config: &Config, name: &str, value: &T, what: &str) {
+    let content = match serde_json::to_vec(value) {
+        Ok(content) => content,
+        Err(err) => {
+            warn!("Failed to serialize {}: {}", what, err);
+            return;
+        }
+    };
+    match config.storage().write_metadata(name, content.into()).await {
+        Ok(()) => {}
+        // the backend doesn't keep metadata, see StorageBackend::write_metadata
+        Err(err) if err.kind() == std::io::ErrorKind::Unsupported => {}
+        Err(err) => warn!("Failed to save {}: {}", what, err),
+    }
+}
+
+/// load_metadata applies the metadata persisted by save_metadata to files
+async fn load_metadata(config: &Config, files: &mut HashMap<String, File>) {
+    let expiries = load_sidecar::<HashMap<String, u64>>(config, EXPIRIES_FILE, "file expiries");
+    for (name, expires) in expiries.await.unwrap_or_default() {
+        if let Some(file) = files.get_mut(&name) {
+            file.expires = Some(UNIX_EPOCH + Duration::from_secs(expires));
+        }
+    }
+    let content_types =
+        load_sidecar::<HashMap<String, String>>(config, CONTENT_TYPES_FILE, "content types");
+    for (name, content_type) in content_types.await.unwrap_or_default() {
+        if let Some(file) = files.get_mut(&name) {
+            file.content_type = Some(content_type);
+        }
+    }
+}
+
+/// load_sidecar reads a file written by save_sidecar, None if it is missing or unreadable
+async fn load_sidecar<T: serde::de::DeserializeOwned>(
+    config: &Config,
+    name: &str,
+    what: &str,
+) -> Option<T> {
+    let content = match config.storage().read_metadata(name).await {
+        Ok(Some(content)) => content,
+        Ok(None) => return None,
+        Err(err) => {
+            warn!("Failed to read {}: {}", what, err);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&content) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            warn!("Failed to parse {}: {}", what, err);
+            None
+        }
+    }
+}
+
+/// sweep_expired deletes expired files from disk and the store every SWEEP_INTERVAL
+async fn sweep_expired(db_handle: FileStore, config: Arc<Config>) {
+    loop {
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+        let now = SystemTime::now();
+        let expired = read_store(&db_handle)
+            .values()
+            .filter(|file| file.expired(now))
+            .map(|file| file.name.clone())
+            .collect::<Vec<String>>();
+        if expired.is_empty() {
+            continue;
+        }
+
+        let mut swept = 0;
+        for name in expired {
+            // the file may have been replaced by a new upload in the meantime
+            let still_expired = |store: &HashMap<String, File>| {
+                store.get(&name).is_some_and(|file| file.expired(now))
+            };
+            if !still_expired(&read_store(&db_handle)) {
+                continue;
+            }
+            if !config.memory_only {
+                match config.storage().delete(&name).await {
+                    Ok(()) => {}
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => {
+                        warn!(file = %name, "failed to delete expired file: {}", err);
+                        continue;
+                    }
+                }
+            }
+            let mut store = write_store(&db_handle);
+            if still_expired(&store) {
+                store.remove(&name);
+                swept += 1;
+                config.events.publish(Action::Deleted, &name);
+            }
+        }
+        save_metadata(&db_handle, &config).await;
+        info!("Swept {} expired File(s)", swept);
+    }
+}
+
+/// persist_error responds to a failed upload of filename, failures while reading the body are the
+/// client's fault, everything else is on our side. Running out of disk space is reported as 507 so
+/// clients can tell it apart from other failures
+fn persist_error(
+    config: &Config,
+    filename: &str,
+    err: anyhow::Error,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    if err.is::<PayloadTooLarge>() {
+        return payload_too_large(config);
+    }
+    if err.is::<FileTooLarge>() {
+        return file_too_large(config, filename);
+    }
+    if let Some(rejected) = err.downcast_ref::<Rejected>() {
+        return error_response(rejected.code, &rejected.msg);
+    }
+    let storage_full = err.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|err| err.kind() == std::io::ErrorKind::StorageFull)
+    });
+    let code = if err.is::<hyper::Error>()
+        || err.is::<multipart::Malformed>()
+        || err.is::<LengthMismatch>()
+    {
+        ErrorCode::BadRequest
+    } else if storage_full {
+        ErrorCode::InsufficientStorage
+    } else {
+        ErrorCode::Internal
+    };
+    error_response(
+        code,
+        &format!("Failed to store file '{}': {}", filename, err),
+    )
+}
+
+/// PayloadTooLarge is returned once a body streamed in exceeds Config::max_body_size
+#[derive(Debug)]
+struct PayloadTooLarge;
+
+impl std::fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("request body exceeds the maximum upload size")
+    }
+}
+
+impl std::error::Error for PayloadTooLarge {}
+
+/// FileTooLarge is returned by FileWriter once the file exceeds Config::upload_limit
+#[derive(Debug)]
+struct FileTooLarge;
+
+impl std::fmt::Display for FileTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("file exceeds the maximum file size")
+    }
+}
+
+impl std::error::Error for FileTooLarge {}
+
+/// Rejected is returned by publish for uploads whose name was taken, removed or replaced by another
+/// request while they streamed in, with the error code and message to respond with
+#[derive(Debug)]
+struct Rejected {
+    code: ErrorCode,
+    msg: String,
+}
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// LengthMismatch is returned once a body turns out to differ in size from its Content-Length
+#[derive(Debug)]
+struct LengthMismatch {
+    expected: u64,
+    received: u64,
+}
+
+impl std::fmt::Display for LengthMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "received {} bytes of a request body with a Content-Length of {}",
+            self.received, self.expected
+        )
+    }
+}
+
+impl std::error::Error for LengthMismatch {}
+
+fn file_too_large(
+    config: &Config,
+    filename: &str,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    error_response(
+        ErrorCode::PayloadTooLarge,
+        &format!(
+            "File '{}' exceeds the maximum file size of {} bytes",
+            filename,
+            config.upload_limit()
+        ),
+    )
+}
+
+fn payload_too_large(config: &Config) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    error_response(
+        ErrorCode::PayloadTooLarge,
+        &format!(
+            "Request body exceeds the maximum upload size of {} bytes",
+            config.max_body_size
+        ),
+    )
+}
+
+/// find_file looks up the file stored as key, falling back to a file whose name only differs in
+/// case with Config::case_insensitive
+fn find_file<'a>(store: &'a HashMap<String, File>, config: &Config, key: &str) -> Option<&'a File> {
+    store.get(key).or_else(|| {
+        config
+            .case_insensitive
+            .then(|| case_collision(store, key, key))
+            .flatten()
+    })
+}
+
+/// case_collision returns a file other than except whose name only differs from key in case, the
+/// first by name in case names colliding were stored before Config::case_insensitive was set
+fn case_collision<'a>(
+    store: &'a HashMap<String, File>,
+    key: &str,
+    except: &str,
+) -> Option<&'a File> {
+    let key = key.to_lowercase();
+    store
+        .values()
+        .filter(|file| file.name != except && file.name.to_lowercase() == key)
+        .min_by(|a, b| a.name.cmp(&b.name))
+}
+
+/// valid_content_type checks that content_type is a media type that can be sent as a header
+fn valid_content_type(content_type: &str) -> bool {
+    content_type.contains('/') && HeaderValue::from_str(content_type).is_ok()
+}
+
+fn invalid_content_type(content_type: &str) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    error_response(
+        ErrorCode::BadRequest,
+        &format!(
+            "Invalid content type {:?}, expected e.g. text/plain",
+            content_type
+        ),
+    )
+}
+
+/// valid_name checks that a name for a new file is neither blank, nor contains blank directories,
+/// control characters or more than MAX_NAME_LENGTH bytes, returning the reason if it isn't
+fn valid_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err(String::from("name must not be empty"));
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(format!("name exceeds {} bytes", MAX_NAME_LENGTH));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(String::from("name must not contain control characters"));
+    }
+    if name
+        .split('/')
+        .any(|segment| !segment.is_empty() && segment.trim().is_empty())
+    {
+        return Err(String::from("name must not contain blank directories"));
+    }
+    Ok(())
+}
+
+/// upload_name sanitizes the name of an upload, rejecting bad paths and names whose existence in
+/// the store doesn't fit mode, with the error code and message to respond with
+fn upload_name(
+    db_handle: &FileStore,
+    config: &Config,
+    name: &str,
+    mode: UploadMode,
+    if_match: Option<&str>,
+) -> Result<String, (ErrorCode, String)> {
+    if let Err(reason) = valid_name(name) {
+        return Err((
+            ErrorCode::BadRequest,
+            format!("Failed to store file {:?}: {}", name, reason),
+        ));
+    }
+    // only ever write below the store directory, regardless of the directories the client prepends
+    let Some(filename) = store_key(name) else {
+        return Err((
+            ErrorCode::BadRequest,
+            format!("Failed to store file with bad path '{}'", name),
+        ));
+    };
+    upload_conflict(&read_store(db_handle), config, &filename, mode, if_match)?;
+    Ok(filename)
+}
+
+/// upload_conflict checks whether filename may be stored in store with mode and if_match, failing
+/// with the error code and message to respond with. Checked before the body of an upload is read
+/// and once more when it is published
+fn upload_conflict(
+    store: &HashMap<String, File>,
+    config: &Config,
+    filename: &str,
+    mode: UploadMode,
+    if_match: Option<&str>,
+) -> Result<(), (ErrorCode, String)> {
+    if let Some(value) = if_match {
+        let etag = store.get(filename).map(|file| file.etag.as_str());
+        if !etag.is_some_and(|etag| if_match_satisfied(value, etag)) {
+            return Err((
+                ErrorCode::PreconditionFailed,
+                format!("File '{}' doesn't match If-Match '{}'", filename, value),
+            ));
+        }
+    }
+    if config.case_insensitive {
+        if let Some(other) = case_collision(store, filename, filename) {
+            return Err((
+                ErrorCode::Conflict,
+                format!(
+                    "File '{}' only differs in case from existing file '{}'",
+                    filename, other.name
+                ),
+            ));
+        }
+    }
+    let exists = store.contains_key(filename);
+    match mode {
+        UploadMode::Create if exists => Err((
+            ErrorCode::Conflict,
+            format!(
+                "File '{}' already exists, use ?overwrite=true or PUT to replace it",
+                filename
+            ),
+        )),
+        UploadMode::Update if !exists => Err((
+            ErrorCode::NotFound,
+            format!("File '{}' not found in store", filename),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// persist writes body to filename in Config::storage frame by frame, so the body is never
+/// required to be in memory as a whole, and returns the writer to be finished by publish. Bodies
+/// differing from content_length fail with LengthMismatch
+async fn persist<B>(
+    config: &Config,
+    filename: String,
+    body: B,
+    content_length: Option<u64>,
+    dry_run: bool,
+) -> Result<FileWriter>
+where
+    B: Body<Data = Bytes>,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut writer = FileWriter::create(config, filename, dry_run).await?;
+    let mut body = pin!(body);
+    while let Some(frame) = body.frame().await {
+        // trailers carry no content
+        let Ok(chunk) = frame?.into_data() else {
+            continue;
+        };
+        writer.write(&chunk).await?;
+    }
+    // a body cut short must never be stored as if it were complete, the writer is dropped
+    // unfinished which discards what was written
+    if let Some(expected) = content_length.filter(|len| *len != writer.size) {
+        return Err(LengthMismatch {
+            expected,
+            received: writer.size,
+        }
+        .into());
+    }
+    Ok(writer)
+}
+
+/// dedup looks for a file with content identical to file in the store and shares it: in memory
+/// both files reference the same buffer and in Config::storage file is linked to the other one.
+/// Names stay independent, replacing or deleting one of them leaves the other untouched. Any
+/// failure leaves file as an independent copy
+async fn dedup(db_handle: &FileStore, config: &Config, file: &mut File) {
+    // equal etags mean equal sha256 digests, candidates are still compared byte by byte in case
+    // the content in storage changed since it was hashed
+    let candidates = read_store(db_handle)
+        .values()
+        .filter(|other| other.etag == file.etag && other.name != file.name)
+        .map(|other| (other.name.clone(), other.content.clone()))
+        .collect::<Vec<(String, Option<Bytes>)>>();
+    for (name, content) in candidates {
+        match same_content(config, file, &name, content.as_ref()).await {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(err) => {
+                warn!(file = %file.name, other = %name, "failed to compare for dedup: {}", err);
+                continue;
+            }
+        }
+        if !config.memory_only {
+            match config.storage().link(&name, &file.name).await {
+                // linked content shares its modification time, keep the store in line with it
+                Ok(modified) => file.modified = modified,
+                // the backend can't share content, so the file keeps its own copy
+                Err(err) if err.kind() == std::io::ErrorKind::Unsupported => return,
+                Err(err) => {
+                    warn!(file = %file.name, other = %name, "failed to dedup: {}", err);
+                    return;
+                }
+            }
+        }
+        if file.content.is_some() && content.is_some() {
+            file.content = content;
+        }
+        return;
+    }
+}
+
+async fn same_content(
+    config: &Config,
+    file: &File,
+    other: &str,
+    other_content: Option<&Bytes>,
+) -> std::io::Result<bool> {
+    let content = match &file.content {
+        Some(content) => content.clone(),
+        None => config.storage().read(&file.name).await?,
+    };
+    let other_content = match other_content {
+        Some(content) => content.clone(),
+        None => config.storage().read(other).await?,
+    };
+    Ok(content == other_content)
+}
+
+/// FileWriter writes a file to Config::storage chunk by chunk, hashing it on the way. The
+/// content is only kept for the resulting File if it doesn't exceed Config::cache_max_size,
+/// files larger than Config::upload_limit fail with FileTooLarge. In memory only mode
+/// nothing is written and the content is always kept, dry runs neither write nor keep anything
+struct FileWriter {
+    /// None in memory only mode and for dry runs
+    out: Option<Box<dyn StorageWriter>>,
+    name: String,
+    sha256: sha256::Sha256,
+    size: u64,
+    cached: Option<Vec<u8>>,
+    cache_max_size: u64,
+    max_size: u64,
+}
+
+impl FileWriter {
+    async fn create(config: &Config, filename: String, dry_run: bool) -> Result<FileWriter> {
+        let out = match config.memory_only || dry_run {
+            true => None,
+            false => Some(config.storage().write(&filename).await?),
+        };
+        Ok(FileWriter {
+            out,
+            name: filename,
+            sha256: sha256::Sha256::default(),
+            size: 0,
+            cached: (!dry_run).then(Vec::new),
+            cache_max_size: match config.memory_only {
+                true => u64::MAX,
+                false => config.cache_max_size,
+            },
+            max_size: config.upload_limit(),
+        })
+    }
+
+    async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        if self.size + chunk.len() as u64 > self.max_size {
+            return Err(FileTooLarge.into());
+        }
+        if let Some(out) = &mut self.out {
+            out.write(chunk).await?;
+        }
+        self.sha256.update(chunk);
+        self.size += chunk.len() as u64;
+        if self.size > self.cache_max_size {
+            self.cached = None;
+        } else if let Some(content) = &mut self.cached {
+            content.extend_from_slice(chunk);
+        }
+        Ok(())
+    }
+
+    async fn finish(self) -> Result<File> {
+        let modified = match self.out {
+            Some(out) => out.finish().await?,
+            None => SystemTime::now(),
+        };
+        let sha256 = sha256::hex(&self.sha256.finish());
+        Ok(File {
+            etag: etag(&sha256),
+            sha256,
+            downloads: Arc::default(),
+            accessed: Arc::new(AtomicU64::new(unix_millis(SystemTime::now()))),
+            expires: None,
+            content_type: None,
+            modified,
+            name: self.name,
+            content: self.cached.map(Bytes::from),
+            size: self.size,
+        })
+    }
+}
+
+/// download responds with the content of file_name, for head_only requests only the headers are
+/// sent, allowing clients to check for existence and size without transferring the content.
+/// Browsers display viewable types inline, unless attachment forces a download prompt. Requests
+/// for several ranges are answered with a multipart/byteranges body holding one part per range
+pub async fn download(
+    db_handle: FileStore,
+    config: &Config,
+    headers: &HeaderMap,
+    file_name: &str,
+    head_only: bool,
+    attachment: bool,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    // edge case if only /file is called
+    if file_name == "file" {
+        return error_response(ErrorCode::BadRequest, "No file path given");
+    }
+
+    let Some(filename) = store_key(file_name) else {
+        return error_response(
+            ErrorCode::BadRequest,
+            &format!("Failed to load file with bad path '{}'", file_name),
+        );
+    };
+
+    let Some(file) = find_file(&read_store(&db_handle), config, &filename)
+        .filter(|file| !file.expired(SystemTime::now()))
+        .cloned()
+    else {
+        metrics::record_lookup(Lookup::Miss);
+        return error_response(
+            ErrorCode::NotFound,
+            &format!("File '{}' not found in store", file_name),
+        );
+    };
+    metrics::record_lookup(match file.content {
+        Some(_) => Lookup::Hit,
+        None => Lookup::Uncached,
+    });
+    file.touch();
+    // the name as stored, which differs from the requested one for case insensitive lookups
+    let filename = file.name.clone();
+
+    // http dates have second granularity, so is the comparison with If-Modified-Since
+    let last_modified = HttpDate::from(file.modified);
+    // If-Modified-Since is only considered by clients not sending If-None-Match, see RFC 7232
+    let not_modified = match headers.get(IF_NONE_MATCH) {
+        Some(value) => value
+            .to_str()
+            .is_ok_and(|value| etag_matches(value, &file.etag)),
+        None => headers
+            .get(IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<HttpDate>().ok())
+            .is_some_and(|since| last_modified <= since),
+    };
+    if not_modified {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(CACHE_CONTROL, &config.cache_control)
+            .header(ETAG, &file.etag)
+            .header(LAST_MODIFIED, last_modified.to_string())
+            .body(full(Bytes::new()))?);
+    }
+
+    let builder = Response::builder()
+        .header(CONTENT_TYPE, file.mime())
+        .header(
+            CONTENT_DISPOSITION,
+            content_disposition(
+                filename.rsplit('/').next().unwrap_or(&filename),
+                file.mime(),
+                attachment,
+            ),
+        )
+        .header(CACHE_CONTROL, &config.cache_control)
+        .header(ETAG, &file.etag)
+        .header(LAST_MODIFIED, last_modified.to_string())
+        .header(X_CONTENT_SHA256, &file.sha256)
+        .header(ACCEPT_RANGES, "bytes");
+    // the range is resolved against the size of the file once, HEAD and GET only differ in
+    // whether the content is sent, so HEAD never has to read files that aren't cached
+    let size = file.size as usize;
+    let ranges = match headers.get(RANGE) {
+        None => None,
+        Some(range) => match range
+            .to_str()
+            .ok()
+            .and_then(|range| parse_ranges(range, size))
+        {
+            Some(ranges) => Some(ranges),
+            None => {
+                let mut res = error_response(
+                    ErrorCode::RangeNotSatisfiable,
+                    &format!(
+                        "Range not satisfiable for file '{}' of {} bytes",
+                        filename, size
+                    ),
+                )?;
+                res.headers_mut().insert(
+                    CONTENT_RANGE,
+                    HeaderValue::from_str(&format!("bytes */{}", size))?,
+                );
+                return Ok(res);
+            }
+        },
+    };
+    let (builder, body) = match (ranges, file.content.clone()) {
+        // ranges address the identity encoding, so partial content is never compressed
+        (Some(mut ranges), content) if ranges.len() == 1 => {
+            let range = ranges.remove(0);
+            let builder = builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+                )
+                .header(CONTENT_LENGTH, range.len());
+            let body = match (head_only, content) {
+                (true, _) => full(Bytes::new()),
+                (false, Some(content)) => full(content.slice(range)),
+                (false, None) => {
+                    stream_content(config, &filename, range.start as u64, range.len() as u64)
+                        .await?
+                }
+            };
+            (builder, body)
+        }
+        (Some(ranges), content) => {
+            let parts = byteranges(&file.sha256, file.mime(), size, ranges);
+            let mut builder = builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_LENGTH, parts.len());
+            // the file's own type moves to the part headers
+            if let Some(headers) = builder.headers_mut() {
+                headers.insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_str(&format!(
+                        "multipart/byteranges; boundary={}",
+                        parts.boundary
+                    ))?,
+                );
+            }
+            let body = match (head_only, content) {
+                (true, _) => full(Bytes::new()),
+                (false, Some(content)) => full(parts.collect(&content)),
+                (false, None) => parts.stream(config, &filename).await?,
+            };
+            (builder, body)
+        }
+        // files too large to be cached are too large to be compressed on every request, they are
+        // streamed from the backend instead of being read into memory
+        (None, None) => {
+            let builder = builder.status(StatusCode::OK).header(CONTENT_LENGTH, size);
+            let body = match head_only {
+                true => full(Bytes::new()),
+                false => stream_content(config, &filename, 0, file.size).await?,
+            };
+            (builder, body)
+        }
+        (None, Some(content)) => {
+            let (builder, content) =
+                encode_body(headers, builder.status(StatusCode::OK), content).await?;
+            (builder, full(content))
+        }
+    };
+    if head_only {
+        return Ok(builder.body(full(Bytes::new()))?);
+    }
+    file.downloads.fetch_add(1, Ordering::Relaxed);
+    metrics::record_download();
+    Ok(builder.body(body)?)
+}
+
+/// stream_content streams len bytes of filename starting at offset from the storage backend, one
+/// chunk at a time so large files are never held in memory as a whole
+async fn stream_content(
+    config: &Config,
+    filename: &str,
+    offset: u64,
+    len: u64,
+) -> Result<BoxBody<Bytes, std::io::Error>> {
+    let content = config.storage().open(filename, offset).await?;
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    let filename = filename.to_string();
+    tokio::spawn(async move {
+        if let Err(err) = send_content(&tx, content, len, &filename).await {
+            // the length is already announced, the client can only tell by the connection closing
+            warn!(file = %filename, "failed to send file: {}", err);
+            let _ = tx.send(Err(err)).await;
+        }
+    });
+    Ok(ChannelBody(rx).boxed())
+}
+
+/// ByteRanges is a multipart/byteranges body, one part per range with its own part headers
+struct ByteRanges {
+    boundary: String,
+    parts: Vec<(String, std::ops::Range<usize>)>,
+    /// closing delimiter following the last part
+    end: String,
+}
+
+/// byteranges lays out the parts for ranges of content of size bytes, the boundary is derived
+/// from the content's sha256 so it can't clash with the content of any part
+fn byteranges(
+    sha256: &str,
+    mime: &str,
+    size: usize,
+    ranges: Vec<std::ops::Range<usize>>,
+) -> ByteRanges {
+    let boundary = format!("cdn-{}", sha256);
+    let parts = ranges
+        .into_iter()
+        .map(|range| {
+            let head = format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary,
+                mime,
+                range.start,
+                range.end - 1,
+                size
+            );
+            (head, range)
+        })
+        .collect();
+    let end = format!("\r\n--{}--\r\n", boundary);
+    ByteRanges {
+        boundary,
+        parts,
+        end,
+    }
+}
+
+impl ByteRanges {
+    /// len returns the size of the whole body
+    fn len(&self) -> usize {
+        let parts = self.parts.iter();
+        parts
+            .map(|(head, range)| head.len() + range.len())
+            .sum::<usize>()
+            + self.end.len()
+    }
+
+    /// collect builds the body from content held in memory
+    fn collect(&self, content: &[u8]) -> Bytes {
+        let mut body = Vec::with_capacity(self.len());
+        for (head, range) in &self.parts {
+            body.extend_from_slice(head.as_bytes());
+            body.extend_from_slice(&content[range.clone()]);
+        }
+        body.extend_from_slice(self.end.as_bytes());
+        Bytes::from(body)
+    }
+
+    /// stream streams the body with the content of every part read from the storage backend,
+    /// like stream_content does for a single range
+    async fn stream(
+        self,
+        config: &Config,
+        filename: &str,
+    ) -> Result<BoxBody<Bytes, std::io::Error>> {
+        // the first range is opened upfront, so missing files still fail the request
+        let Some((_, first)) = self.parts.first() else {
+            return Ok(full(Bytes::from(self.end)));
+        };
+        let storage = config.storage();
+        let mut content = Some(storage.open(filename, first.start as u64).await?);
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let filename = filename.to_string();
+        tokio::spawn(async move {
+            let sent = async {
+                for (head, range) in self.parts {
+                    tx.send(Ok(Bytes::from(head)))
+                        .await
+                        .map_err(|_| client_gone())?;
+                    let reader = match content.take() {
+                        Some(reader) => reader,
+                        None => storage.open(&filename, range.start as u64).await?,
+                    };
+                    send_content(&tx, reader, range.len() as u64, &filename).await?;
+                }
+                tx.send(Ok(Bytes::from(self.end)))
+                    .await
+                    .map_err(|_| client_gone())
+            };
+            if let Err(err) = sent.await {
+                warn!(file = %filename, "failed to send file: {}", err);
+                let _ = tx.send(Err(err)).await;
+            }
+        });
+        Ok(ChannelBody(rx).boxed())
+    }
+}
+
+/// EncodedFile is the body of downloads with ?encoding=base64
+#[derive(Serialize)]
+struct EncodedFile<'a> {
+    name: &'a str,
+    content_type: &'a str,
+    /// content encoded as standard base64 with padding
+    content: String,
+}
+
+/// download_base64 responds with the content of file_name encoded as base64 inside a json body,
+/// for clients that can't consume anything but json. The whole body is built in memory, so files
+/// larger than Config::upload_limit are refused with 413
+pub async fn download_base64(
+    db_handle: FileStore,
+    config: &Config,
+    file_name: &str,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let Some(filename) = store_key(file_name) else {
+        return error_response(
+            ErrorCode::BadRequest,
+            &format!("Failed to load file with bad path '{}'", file_name),
+        );
+    };
+    let Some(file) = find_file(&read_store(&db_handle), config, &filename)
+        .filter(|file| !file.expired(SystemTime::now()))
+        .cloned()
+    else {
+        metrics::record_lookup(Lookup::Miss);
+        return error_response(
+            ErrorCode::NotFound,
+            &format!("File '{}' not found in store", file_name),
+        );
+    };
+    metrics::record_lookup(match file.content {
+        Some(_) => Lookup::Hit,
+        None => Lookup::Uncached,
+    });
+    file.touch();
+    // the name as stored, which differs from the requested one for case insensitive lookups
+    let filename = file.name.clone();
+    if file.size > config.upload_limit() {
+        return error_response(
+            ErrorCode::PayloadTooLarge,
+            &format!(
+                "File '{}' exceeds the maximum size of {} bytes for base64 downloads, download it without ?encoding=base64 instead",
+                filename,
+                config.upload_limit()
+            ),
+        );
+    }
+
+    let content = match &file.content {
+        Some(content) => content.clone(),
+        None => config.storage().read(&filename).await?,
+    };
+    let (content_type, body) = serialize(&EncodedFile {
+        name: &file.name,
+        content_type: file.mime(),
+        content: base64(&content),
+    })?;
+    file.downloads.fetch_add(1, Ordering::Relaxed);
+    metrics::record_download();
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, content_type)
+        .header(CACHE_CONTROL, &config.cache_control)
+        .header(ETAG, &file.etag)
+        .header(X_CONTENT_SHA256, &file.sha256)
+        .body(full(body))?)
+}
+
+#[derive(Serialize)]
+struct ExistsResponse {
+    exists: bool,
+}
+
+/// exists responds with whether file_name is in the store, always with 200 so clients polling
+/// for a file don't have to treat 404 as control flow. Names that can't be stored never exist
+pub fn exists(
+    db_handle: &FileStore,
+    config: &Config,
+    file_name: &str,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let exists = store_key(file_name).is_some_and(|filename| {
+        find_file(&read_store(db_handle), config, &filename)
+            .is_some_and(|file| !file.expired(SystemTime::now()))
+    });
+    serialized_response(StatusCode::OK, &ExistsResponse { exists })
+}
+
+/// checksum responds with just the hex encoded SHA-256 digest of file_name
+pub fn checksum(
+    db_handle: FileStore,
+    config: &Config,
+    file_name: &str,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let Some(filename) = store_key(file_name) else {
+        return error_response(
+            ErrorCode::BadRequest,
+            &format!(
+                "Failed to get checksum of file with bad path '{}'",
+                file_name
+            ),
+        );
+    };
+    let Some(sha256) = find_file(&read_store(&db_handle), config, &filename)
+        .filter(|file| !file.expired(SystemTime::now()))
+        .map(|file| file.sha256.clone())
+    else {
+        return error_response(
+            ErrorCode::NotFound,
+            &format!("File '{}' not found in store", file_name),
+        );
+    };
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .header(X_CONTENT_SHA256, &sha256)
+        .body(full(sha256))?)
+}
+
+/// archive streams every file in the store as a single tar archive, entries not cached are read
+/// from disk one chunk at a time so the archive is never held in memory
+pub fn archive(
+    db_handle: FileStore,
+    config: &Config,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let mut files = read_store(&db_handle)
+        .values()
+        .map(File::metadata)
+        .collect::<Vec<File>>();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    let storage = config.storage();
+    tokio::spawn(async move {
+        for file in files {
+            // cached files are sent from memory, one at a time to not clone the whole store
+            let cached = read_store(&db_handle)
+                .get(&file.name)
+                .and_then(|current| current.content.clone())
+                .filter(|content| content.len() as u64 == file.size);
+            if let Err(err) = archive_entry(&tx, storage.as_ref(), &file, cached).await {
+                warn!(file = %file.name, "failed to archive file: {}", err);
+                // the size is already announced in the header, the archive can't be continued
+                let _ = tx.send(Err(err)).await;
+                return;
+            }
+        }
+        let _ = tx.send(Ok(Bytes::from_static(&tar::END))).await;
+    });
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/x-tar")
+        .header(
+            CONTENT_DISPOSITION,
+            "attachment; filename=\"cdn-archive.tar\"",
+        )
+        .body(ChannelBody(rx).boxed())?)
+}
+
+/// archive_entry sends the tar header, content and padding of file to tx, the content is read from
+/// storage unless cached is given. Exactly File::size bytes of content are sent even if the file
+/// changed in the meantime
+async fn archive_entry(
+    tx: &tokio::sync::mpsc::Sender<std::io::Result<Bytes>>,
+    storage: &dyn StorageBackend,
+    file: &File,
+    cached: Option<Bytes>,
+) -> std::io::Result<()> {
+    let mtime = file
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    tx.send(Ok(tar::header(&file.name, file.size, mtime).into()))
+        .await
+        .map_err(|_| client_gone())?;
+
+    if let Some(content) = cached {
+        tx.send(Ok(content)).await.map_err(|_| client_gone())?;
+    } else {
+        let content = storage.open(&file.name, 0).await?;
+        send_content(tx, content, file.size, &file.name).await?;
+    }
+
+    let padding = tar::padding(file.size);
+    if padding > 0 {
+        tx.send(Ok(Bytes::from(vec![0; padding])))
+            .await
+            .map_err(|_| client_gone())?;
+    }
+    Ok(())
+}
+
+fn client_gone() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::BrokenPipe, "client went away")
+}
+
+/// send_content sends exactly len bytes of content to tx in chunks, failing if content ends
+/// before, e.g. because the file named name was truncated in the meantime
+async fn send_content(
+    tx: &tokio::sync::mpsc::Sender<std::io::Result<Bytes>>,
+    content: impl tokio::io::AsyncRead + Unpin,
+    len: u64,
+    name: &str,
+) -> std::io::Result<()> {
+    let mut content = content.take(len);
+    let mut remaining = len;
+    while remaining > 0 {
+        let mut chunk = vec![0; remaining.min(64 * 1024) as usize];
+        let read = content.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("'{}' shrunk while being sent", name),
+            ));
+        }
+        chunk.truncate(read);
+        remaining -= read as u64;
+        tx.send(Ok(chunk.into())).await.map_err(|_| client_gone())?;
+    }
+    Ok(())
+}
+
+pub async fn delete(
+    db_handle: FileStore,
+    config: &Config,
+    file_name: &str,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let Some(filename) = store_key(file_name) else {
+        return error_response(
+            ErrorCode::BadRequest,
+            &format!("Failed to delete file with bad path '{}'", file_name),
+        );
+    };
+
+    let _publishing = PUBLISHING.lock().await;
+    if !read_store(&db_handle).contains_key(&filename) {
+        return error_response(
+            ErrorCode::NotFound,
+            &format!("File '{}' not found in store", file_name),
+        );
+    }
+
+    if !config.memory_only {
+        match config.storage().delete(&filename).await {
+            Ok(()) => {}
+            // already gone from disk, only the entry in the store is left to remove
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let removed = write_store(&db_handle).remove(&filename);
+    if removed.is_some() {
+        config.events.publish(Action::Deleted, &filename);
+    }
+    if removed.is_some_and(|file| file.has_metadata()) {
+        save_metadata(&db_handle, config).await;
+    }
+
+    response(StatusCode::OK, &format!("Deleted file '{}'", filename))
+}
+
+/// rename moves file_name to the key to via StorageBackend::rename. Fails if to already exists
+pub async fn rename(
+    db_handle: FileStore,
+    config: &Config,
+    file_name: &str,
+    to: &str,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    if let Err(reason) = valid_name(to) {
+        return error_response(
+            ErrorCode::BadRequest,
+            &format!(
+                "Failed to rename file '{}' to {:?}: {}",
+                file_name, to, reason
+            ),
+        );
+    }
+    let (Some(from), Some(to)) = (store_key(file_name), store_key(to)) else {
+        return error_response(
+            ErrorCode::BadRequest,
+            &format!(
+                "Failed to rename file '{}' with bad path to '{}'",
+                file_name, to
+            ),
+        );
+    };
+
+    let _publishing = PUBLISHING.lock().await;
+    {
+        let lock = read_store(&db_handle);
+        if lock
+            .get(&from)
+            .is_none_or(|file| file.expired(SystemTime::now()))
+        {
+            return error_response(
+                ErrorCode::NotFound,
+                &format!("File '{}' not found in store", file_name),
+            );
+        }
+        if lock.contains_key(&to) {
+            return error_response(
+                ErrorCode::Conflict,
+                &format!("File '{}' already exists", to),
+            );
+        }
+        // renaming a file to a different case of its own name is fine
+        if let Some(other) = case_collision(&lock, &to, &from).filter(|_| config.case_insensitive) {
+            return error_response(
+                ErrorCode::Conflict,
+                &format!(
+                    "File '{}' only differs in case from existing file '{}'",
+                    to, other.name
+                ),
+            );
+        }
+    }
+
+    if !config.memory_only {
+        config.storage().rename(&from, &to).await?;
+    }
+    let has_metadata = {
+        let mut lock = write_store(&db_handle);
+        match lock.remove(&from) {
+            Some(mut file) => {
+                file.name = to.clone();
+                let has_metadata = file.has_metadata();
+                lock.insert(to.clone(), file);
+                config.events.publish(Action::Deleted, &from);
+                config.events.publish(Action::Uploaded, &to);
+                has_metadata
+            }
+            None => false,
+        }
+    };
+    if has_metadata {
+        save_metadata(&db_handle, config).await;
+    }
+
+    response(
+        StatusCode::OK,
+        &format!("Renamed file '{}' to '{}'", from, to),
+    )
+}
+
+/// clear deletes every file in the store. Files are removed from the store one by one, each only
+/// after it is gone from disk, so a failing deletion leaves the store and the disk in agreement
+pub async fn clear(
+    db_handle: FileStore,
+    config: &Config,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let names = read_store(&db_handle)
+        .keys()
+        .cloned()
+        .collect::<Vec<String>>();
+    let storage = config.storage();
+    let mut deleted = 0;
+    for name in names {
+        let removed = match config.memory_only {
+            true => Ok(()),
+            false => storage.delete(&name).await,
+        };
+        match removed {
+            Ok(()) => {}
+            // deleted concurrently, that's what we wanted anyway
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => {
+                return error_response(
+                    ErrorCode::Internal,
+                    &format!(
+                        "Failed to delete file '{}' after deleting {} files: {}",
+                        name, deleted, err
+                    ),
+                )
+            }
+        }
+        if write_store(&db_handle).remove(&name).is_some() {
+            deleted += 1;
+            config.events.publish(Action::Deleted, &name);
+        }
+    }
+    save_metadata(&db_handle, config).await;
+
+    let msg = match deleted {
+        1 => String::from("Deleted 1 file"),
+        n => format!("Deleted {} files", n),
+    };
+    serialized_response(
+        StatusCode::OK,
+        &CdnResponse {
+            msg: &msg,
+            code: None,
+            files: None,
+            names: None,
+            total: Some(deleted),
+        },
+    )
+}
+
+/// all lists the metadata of the files in the store, supporting the query parameters:
+///
+/// - prefix: only list files whose name starts with prefix
+/// - sort: order by "name" (default) or "size", ties in size are ordered by name
+/// - limit: page size, defaults to DEFAULT_PAGE_SIZE and is capped at MAX_PAGE_SIZE
+/// - offset: number of files to skip
+/// - fields: "name" lists just the names of the files, without copying out their metadata
+///
+/// total always holds the number of files matching prefix, regardless of pagination. Clients
+/// accepting application/x-ndjson get one file per line streamed instead, without a default limit
+pub async fn all(
+    db: FileStore,
+    req: &Request<hyper::body::Incoming>,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let params = query_params(req.uri());
+    let ndjson = accepts(req.headers(), "application/x-ndjson");
+    let default_limit = match ndjson {
+        true => usize::MAX,
+        false => DEFAULT_PAGE_SIZE,
+    };
+    let Some(limit) = usize_param(&params, "limit", default_limit).filter(|l| *l > 0) else {
+        return error_response(
+            ErrorCode::BadRequest,
+            "Invalid limit, expected a positive number",
+        );
+    };
+    let Some(offset) = usize_param(&params, "offset", 0) else {
+        return error_response(
+            ErrorCode::BadRequest,
+            "Invalid offset, expected a non negative number",
+        );
+    };
+
+    let sort = params.get("sort").map(String::as_str).unwrap_or("name");
+    if !matches!(sort, "name" | "size") {
+        return error_response(
+            ErrorCode::BadRequest,
+            &format!("Unknown sort '{}', expected 'name' or 'size'", sort),
+        );
+    }
+    let prefix = params.get("prefix").map(String::as_str).unwrap_or_default();
+    let names_only = match params.get("fields").map(String::as_str) {
+        None => false,
+        Some("name") => true,
+        Some(fields) => {
+            return error_response(
+                ErrorCode::BadRequest,
+                &format!("Unknown fields '{}', expected 'name'", fields),
+            )
+        }
+    };
+
+    if ndjson {
+        return all_ndjson(db, prefix, sort, offset, limit, names_only);
+    }
+
+    // the listing is serialized straight from the store, which stays locked until then
+    let (content_type, body) = {
+        let now = SystemTime::now();
+        let handle = read_store(&db);
+        let mut matching = handle
+            .values()
+            .filter(|file| file.name.starts_with(prefix) && !file.expired(now))
+            .collect::<Vec<&File>>();
+        match sort {
+            "size" => {
+                matching.sort_by(|a, b| a.size.cmp(&b.size).then_with(|| a.name.cmp(&b.name)))
+            }
+            _ => matching.sort_by(|a, b| a.name.cmp(&b.name)),
+        }
+        let page = matching.iter().skip(offset).take(limit.min(MAX_PAGE_SIZE));
+        if names_only {
+            let names = page.map(|file| file.name.as_str()).collect::<Vec<&str>>();
+            let response = CdnResponse {
+                msg: match &names.len() {
+                    0 => "Got no files",
+                    1 => "Got 1 file",
+                    _ => &format!("Got {} files", names.len()),
+                },
+                code: None,
+                files: None,
+                names: Some(names),
+                total: Some(matching.len()),
+            };
+            serialize(&response)?
+        } else {
+            let files = page.map(|file| file.metadata()).collect::<Vec<File>>();
+            let response = CdnResponse {
+                msg: match &files.len() {
+                    0 => "Got no files",
+                    1 => "Got 1 file",
+                    _ => &format!("Got {} files", files.len()),
+                },
+                code: None,
+                files: Some(files),
+                names: None,
+                total: Some(matching.len()),
+            };
+            serialize(&response)?
+        }
+    };
+
+    let (builder, body) = encode_body(
+        req.headers(),
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, content_type),
+        body.into(),
+    )
+    .await?;
+    Ok(builder.body(full(body))?)
+}
+
+/// popular lists the most downloaded files, most downloaded first, ?limit=<n> files at most
+pub async fn popular(
+    db: FileStore,
+    req: &Request<hyper::body::Incoming>,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let params = query_params(req.uri());
+    let Some(limit) = usize_param(&params, "limit", DEFAULT_POPULAR_SIZE).filter(|l| *l > 0) else {
+        return error_response(
+            ErrorCode::BadRequest,
+            "Invalid limit, expected a positive number",
+        );
+    };
+
+    let now = SystemTime::now();
+    let mut files = read_store(&db)
+        .values()
+        .filter(|file| !file.expired(now))
+        .map(|file| (file.downloads.load(Ordering::Relaxed), file.metadata()))
+        .collect::<Vec<(u64, File)>>();
+    // counts are read once up front, downloads racing the sort can't make it inconsistent
+    files.sort_by(|(a, a_file), (b, b_file)| b.cmp(a).then_with(|| a_file.name.cmp(&b_file.name)));
+    let files = files
+        .into_iter()
+        .take(limit.min(MAX_PAGE_SIZE))
+        .map(|(_, file)| file)
+        .collect::<Vec<File>>();
+    let response = CdnResponse {
+        msg: match &files.len() {
+            0 => "Got no files",
+            1 => "Got 1 file",
+            _ => &format!("Got {} files", files.len()),
+        },
+        code: None,
+        files: Some(files),
+        names: None,
+        total: None,
+    };
+
+    let (content_type, body) = serialize(&response)?;
+    let (builder, body) = encode_body(
+        req.headers(),
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, content_type),
+        body.into(),
+    )
+    .await?;
+    Ok(builder.body(full(body))?)
+}
+
+/// all_ndjson streams the listing of all as newline delimited json. Only the names of the
+/// matching files are collected upfront, their metadata is serialized batch by batch while the
+/// response is sent, files deleted in the meantime are left out
+fn all_ndjson(
+    db: FileStore,
+    prefix: &str,
+    sort: &str,
+    offset: usize,
+    limit: usize,
+    names_only: bool,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let now = SystemTime::now();
+    let mut names = read_store(&db)
+        .values()
+        .filter(|file| file.name.starts_with(prefix) && !file.expired(now))
+        .map(|file| (file.name.clone(), file.size))
+        .collect::<Vec<(String, u64)>>();
+    match sort {
+        "size" => names.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0))),
+        _ => names.sort_by(|a, b| a.0.cmp(&b.0)),
+    }
+    let names = names
+        .into_iter()
+        .skip(offset)
+        .take(limit)
+        .map(|(name, _)| name)
+        .collect::<Vec<String>>();
+
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    tokio::spawn(async move {
+        for batch in names.chunks(256) {
+            let mut lines = Vec::new();
+            if names_only {
+                for name in batch {
+                    if serde_json::to_writer(&mut lines, name).is_ok() {
+                        lines.push(b'\n');
+                    }
+                }
+            } else {
+                let store = read_store(&db);
+                for file in batch.iter().filter_map(|name| store.get(name)) {
+                    if serde_json::to_writer(&mut lines, &file.metadata()).is_ok() {
+                        lines.push(b'\n');
+                    }
+                }
+            }
+            if tx.send(Ok(lines.into())).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/x-ndjson")
+        .body(ChannelBody(rx).boxed())?)
+}
+
+/// bind_addr resolves the address the server listens on, the environment variables CDN_HOST and
+/// CDN_PORT take precedence over the defaults of 127.0.0.1 and 8080
+fn bind_addr() -> Result<SocketAddr> {
+    let host: IpAddr = match std::env::var("CDN_HOST") {
+        // ipv6 hosts may be given in their bracketed url form, e.g. [::1]
+        Ok(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .with_context(|| format!("Invalid CDN_HOST '{}', expected an ip address", host))?,
+        Err(_) => IpAddr::from([127, 0, 0, 1]),
+    };
+    let port: u16 = match std::env::var("CDN_PORT") {
+        Ok(port) => port
+            .parse()
+            .with_context(|| format!("Invalid CDN_PORT '{}', expected a port number", port))?,
+        Err(_) => 8080,
+    };
+    Ok(SocketAddr::new(host, port))
+}
+
+/// request_id returns the X-Request-Id the client or a proxy in front of us sent, generating a new
+/// one if there is none or it isn't a short printable ascii string
+fn request_id(headers: &HeaderMap) -> String {
+    static REQUESTS: AtomicU64 = AtomicU64::new(0);
+    headers
+        .get(X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .filter(|id| id.bytes().all(|b| b.is_ascii_graphic()))
+        .map(String::from)
+        .unwrap_or_else(|| {
+            format!(
+                "{:x}-{:x}",
+                std::process::id(),
+                REQUESTS.fetch_add(1, Ordering::Relaxed)
+            )
+        })
+}
+
+/// listen binds a listener to addr. Listeners on the unspecified ipv6 address :: are made dual
+/// stack, accepting ipv4 connections as ipv4-mapped addresses, if the platform supports it
+pub fn listen(addr: SocketAddr) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        if let Err(err) = socket.set_only_v6(false) {
+            warn!("Failed to accept ipv4 connections on {}: {}", addr, err);
+        }
+    }
+    // allows restarting right away, while connections of the previous process are in TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// Listener is a bound socket serve accepts connections on
+pub enum Listener {
+    Tcp(TcpListener),
+    /// unix domain socket bound to the path, which is removed once serve returns
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Listener {
+        Listener::Tcp(listener)
+    }
+}
+
+/// listen_unix binds a unix domain socket to path, replacing a socket left behind by a previous
+/// process that didn't shut down cleanly
+#[cfg(unix)]
+pub fn listen_unix(path: &Path) -> Result<Listener> {
+    use std::os::unix::fs::FileTypeExt;
+    // never remove anything but a socket, path may have been mistyped
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    Ok(Listener::Unix(
+        UnixListener::bind(path)?,
+        path.to_path_buf(),
+    ))
+}
+
+/// forwarded_for returns the leftmost, i.e. originating, client ip of the X-Forwarded-For header
+fn forwarded_for(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(X_FORWARDED_FOR)?.to_str().ok()?;
+    let client = value.split(',').next()?.trim();
+    client.parse::<IpAddr>().ok().map(|ip| ip.to_string())
+}
+
+/// serve accepts connections on listener until shutdown resolves, answering every request via
+/// response_handler. The store is loaded via init_store meanwhile, until it is ready only /health
+/// and /ready are answered, anything else fails with 503. Once loaded, expired files are swept
+/// and, if configured, the store directory is rescanned in the background. Once shutdown
+/// resolved, open connections get SHUTDOWN_TIMEOUT to finish their requests
+pub async fn serve(
+    listener: impl Into<Listener>,
+    config: Arc<Config>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let listener = listener.into();
+    let db = FileStore::default();
+    let ready = Arc::new(AtomicBool::new(false));
+    let mut loading = pin!(init_store(&config));
+    let mut tasks = Vec::new();
+
+    match &listener {
+        Listener::Tcp(listener) => info!("Listening on http://{}", listener.local_addr()?),
+        #[cfg(unix)]
+        Listener::Unix(_, path) => info!("Listening on unix:{}", path.display()),
+    }
+    let graceful = GracefulShutdown::new();
+    let connections = config
+        .max_connections
+        .map(|max| Arc::new(Semaphore::new(max)));
+    let http = http_builder(&config);
+    let mut shutdown = pin!(shutdown);
+    loop {
+        tokio::select! {
+            conn = accept(&listener, &connections, &db, &ready, &config, &http, &graceful) => {
+                conn.context("Failed to await stream accepting")?
+            }
+            loaded = &mut loading, if !ready.load(Ordering::Acquire) => {
+                *write_store(&db) = std::mem::take(&mut *write_store(&loaded?));
+                ready.store(true, Ordering::Release);
+                tasks.push(tokio::spawn(sweep_expired(
+                    Arc::clone(&db),
+                    Arc::clone(&config),
+                )));
+                if let Some(interval) = config.watch_interval.filter(|_| !config.memory_only) {
+                    tasks.push(tokio::spawn(watch_store(
+                        Arc::clone(&db),
+                        Arc::clone(&config),
+                        interval,
+                    )));
+                }
+            }
+            _ = &mut shutdown => break,
+        };
+    }
+
+    #[cfg(unix)]
+    if let Listener::Unix(_, path) = &listener {
+        if let Err(err) = fs::remove_file(path).await {
+            warn!("Failed to remove socket {}: {}", path.display(), err);
+        }
+    }
+    drop(listener);
+    // event streams never finish on their own
+    config.events.close();
+    info!("Shutting down, waiting for open connections to finish");
+    tokio::select! {
+        _ = graceful.shutdown() => info!("All connections closed"),
+        _ = tokio::time::sleep(SHUTDOWN_TIMEOUT) => {
+            warn!(
+                "Timed out after {}s waiting for connections to close",
+                SHUTDOWN_TIMEOUT.as_secs()
+            );
+        }
+    }
+    for task in tasks {
+        task.abort();
+    }
+    Ok(())
+}
+
+/// http_builder configures how connections are served according to the http settings of config
+fn http_builder(config: &Config) -> http1::Builder {
+    let mut builder = http1::Builder::new();
+    builder
+        .timer(TokioTimer::new())
+        .keep_alive(config.keep_alive)
+        .max_headers(config.max_headers)
+        .preserve_header_case(config.preserve_header_case)
+        .header_read_timeout(config.header_read_timeout);
+    if let Some(size) = config.max_buf_size {
+        builder.max_buf_size(size);
+    }
+    builder
+}
+
+/// accept waits for the next connection on listener and spawns serving it
+async fn accept(
+    listener: &Listener,
+    connections: &Option<Arc<Semaphore>>,
+    db: &FileStore,
+    ready: &Arc<AtomicBool>,
+    config: &Arc<Config>,
+    http: &http1::Builder,
+    graceful: &GracefulShutdown,
+) -> std::io::Result<()> {
+    // held by the connection task, so a new connection is only accepted once a permit is free
+    let permit = match connections {
+        Some(connections) => Some(
+            Arc::clone(connections)
+                .acquire_owned()
+                .await
+                .map_err(std::io::Error::other)?,
+        ),
+        None => None,
+    };
+    let conn = Connection {
+        db,
+        ready,
+        config,
+        http,
+        graceful,
+        permit,
+    };
+    match listener {
+        Listener::Tcp(listener) => {
+            let (stream, addr) = listener.accept().await?;
+            serve_stream(stream, addr.to_string(), conn);
+        }
+        // peers of unix sockets are usually unnamed and never have an ip, log the socket instead
+        #[cfg(unix)]
+        Listener::Unix(listener, _) => {
+            let (stream, _) = listener.accept().await?;
+            serve_stream(stream, String::from("unix"), conn);
+        }
+    }
+    Ok(())
+}
+
+/// Connection holds what serve_stream shares with every other connection
+struct Connection<'a> {
+    db: &'a FileStore,
+    ready: &'a Arc<AtomicBool>,
+    config: &'a Arc<Config>,
+    http: &'a http1::Builder,
+    graceful: &'a GracefulShutdown,
+    /// released once the connection closed, see Config::max_connections
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+/// serve_stream serves the http connection on stream in a task of its own, addr is the peer
+/// logged with every request
+fn serve_stream<S>(stream: S, addr: String, conn: Connection)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let Connection {
+        db,
+        ready,
+        config,
+        http,
+        graceful,
+        permit,
+    } = conn;
+    let stream = IdleStream::new(stream);
+    let last_active = Arc::clone(&stream.last_active);
+    let io = TokioIo::new(stream);
+    let addr: Arc<str> = Arc::from(addr);
+    let peer = Arc::clone(&addr);
+    let idle_timeout = config.idle_timeout;
+    let db_handle = db.clone();
+    let ready = ready.clone();
+    let config = config.clone();
+    let conn = http.serve_connection(
+        io,
+        service_fn(move |req| {
+            let method = req.method().to_string();
+            let path = req.uri().path().to_string();
+            let uri = req
+                .uri()
+                .path_and_query()
+                .map_or(path.clone(), |uri| uri.to_string());
+            let version = req.version();
+            let access_log = config.access_log;
+            let id = request_id(req.headers());
+            // behind a trusted proxy the peer is the proxy, which is kept as well
+            let forwarded = config
+                .trust_forwarded
+                .then(|| forwarded_for(req.headers()))
+                .flatten();
+            let (addr, proxy) = match forwarded {
+                Some(client) => (Arc::from(client), Some(Arc::clone(&addr))),
+                None => (Arc::clone(&addr), None),
+            };
+            let span = info_span!(
+                "request",
+                id = %id,
+                method = %method,
+                path = %path,
+                peer = %addr,
+                proxy = field::Empty,
+                status = field::Empty,
+                size = field::Empty,
+            );
+            if let Some(proxy) = &proxy {
+                span.record("proxy", proxy.as_ref());
+            }
+            let res = response_handler(
+                req,
+                Arc::clone(&db_handle),
+                Arc::clone(&ready),
+                Arc::clone(&config),
+            )
+            .instrument(span.clone());
+            async move {
+                let mut r = res.await;
+                if let Ok(ok) = &mut r {
+                    if let Ok(value) = HeaderValue::from_str(&id) {
+                        ok.headers_mut().insert(X_REQUEST_ID, value);
+                    }
+                    let size = ok.body().size_hint().exact().unwrap_or(0);
+                    span.record("status", ok.status().as_u16());
+                    span.record("size", size);
+                    metrics::record_request(&method, ok.status());
+                    let entry = AccessLog {
+                        id: &id,
+                        method: &method,
+                        path: &path,
+                        uri: &uri,
+                        version,
+                        status: ok.status(),
+                        size,
+                        peer: &addr,
+                    };
+                    span.in_scope(|| log::access(access_log, &entry));
+                }
+                r
+            }
+        }),
+    );
+    // watching lets in-flight requests finish before the connection is closed on shutdown
+    let conn = graceful.watch(conn);
+    tokio::task::spawn(async move {
+        let idle = async {
+            match idle_timeout {
+                Some(timeout) => idle(&last_active, timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            res = conn => {
+                if let Err(err) = res {
+                    error!("Error serving connection: {:?}", err);
+                }
+            }
+            _ = idle => info!(peer = %peer, "closing idle connection"),
+        }
+        drop(permit);
+    });
+}
+
+/// IdleStream records when data was last read from or written to the stream it wraps
+struct IdleStream<S> {
+    stream: S,
+    /// shared with the task enforcing Config::idle_timeout
+    last_active: Arc<LastActive>,
+}
+
+/// LastActive is the point in time a connection was last active, see IdleStream
+struct LastActive {
+    created: tokio::time::Instant,
+    millis: AtomicU64,
+}
+
+impl LastActive {
+    fn touch(&self) {
+        let millis = self.created.elapsed().as_millis() as u64;
+        self.millis.store(millis, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> tokio::time::Instant {
+        self.created + Duration::from_millis(self.millis.load(Ordering::Relaxed))
+    }
+}
+
+impl<S> IdleStream<S> {
+    fn new(stream: S) -> IdleStream<S> {
+        IdleStream {
+            stream,
+            last_active: Arc::new(LastActive {
+                created: tokio::time::Instant::now(),
+                millis: AtomicU64::new(0),
+            }),
+        }
+    }
+}
+
+impl<S: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for IdleStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.stream).poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            self.last_active.touch();
+        }
+        res
+    }
+}
+
+impl<S: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for IdleStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.stream).poll_write(cx, buf);
+        if matches!(res, Poll::Ready(Ok(n)) if n > 0) {
+            self.last_active.touch();
+        }
+        res
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// idle resolves once last_active lies timeout in the past
+async fn idle(last_active: &LastActive, timeout: Duration) {
+    loop {
+        let deadline = last_active.get() + timeout;
+        if tokio::time::Instant::now() >= deadline {
+            return;
+        }
+        tokio::time::sleep_until(deadline).await;
+    }
+}
@@ -0,0 +1,307 @@
+//! Minimal tracing subscriber, printing every event as a single line to stdout, prefixed with a
+//! timestamp, the level and the spans the event was emitted in, including their fields.
+//!
+//! Events are filtered via RUST_LOG, which holds comma separated directives of either a bare
+//! level applying to all targets or target=level, e.g. "warn,cdn=debug". The directive with the
+//! longest matching target prefix wins, without RUST_LOG everything at info and above is logged.
+//!
+//! Access log lines in the common and json formats are emitted to the cdn::access target and
+//! printed as is, so they can be parsed by existing tooling.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+use httpdate::HttpDate;
+use hyper::StatusCode;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{info, Event, Metadata, Subscriber};
+
+/// target of access log lines that are printed without any prefix
+const ACCESS_TARGET: &str = "cdn::access";
+
+/// AccessLogFormat selects how answered requests are logged, see Config::access_log
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// pipe delimited columns, prefixed like any other event
+    Pipe,
+    /// Common Log Format as written by e.g. apache and nginx
+    Common,
+    /// a json object per line
+    Json,
+}
+
+/// UnknownFormat is returned for names not matching any AccessLogFormat
+#[derive(Debug)]
+pub struct UnknownFormat;
+
+impl std::fmt::Display for UnknownFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected one of pipe, common or json")
+    }
+}
+
+impl std::error::Error for UnknownFormat {}
+
+impl std::str::FromStr for AccessLogFormat {
+    type Err = UnknownFormat;
+
+    fn from_str(value: &str) -> Result<AccessLogFormat, UnknownFormat> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "pipe" => Ok(AccessLogFormat::Pipe),
+            "common" | "clf" => Ok(AccessLogFormat::Common),
+            "json" => Ok(AccessLogFormat::Json),
+            _ => Err(UnknownFormat),
+        }
+    }
+}
+
+/// AccessLog holds the details of an answered request
+pub struct AccessLog<'a> {
+    pub id: &'a str,
+    pub method: &'a str,
+    pub path: &'a str,
+    /// path and query as requested
+    pub uri: &'a str,
+    pub version: hyper::Version,
+    pub status: StatusCode,
+    /// size of the response body in bytes
+    pub size: u64,
+    /// address of the peer, either ip:port or "unix"
+    pub peer: &'a str,
+}
+
+/// access logs an answered request in format, meant to be called in the span of the request
+pub fn access(format: AccessLogFormat, entry: &AccessLog) {
+    match format {
+        AccessLogFormat::Pipe => info!(
+            target: "cdn",
+            "|{: ^5}|{: ^7}| {: <25} | {: >4}b | {} | {}",
+            entry.status.as_u16(),
+            entry.method,
+            entry.path,
+            entry.size,
+            entry.peer,
+            entry.id,
+        ),
+        AccessLogFormat::Common => {
+            // the peer's ip with the port stripped, if any
+            let host = entry
+                .peer
+                .parse::<std::net::SocketAddr>()
+                .map_or(entry.peer.to_string(), |addr| addr.ip().to_string());
+            info!(
+                target: ACCESS_TARGET,
+                "{} - - [{}] \"{} {} {:?}\" {} {}",
+                host,
+                clf_time(SystemTime::now()),
+                entry.method,
+                entry.uri,
+                entry.version,
+                entry.status.as_u16(),
+                entry.size,
+            )
+        }
+        AccessLogFormat::Json => {
+            let line = serde_json::json!({
+                "time": crate::rfc3339(SystemTime::now()),
+                "id": entry.id,
+                "method": entry.method,
+                "uri": entry.uri,
+                "version": format!("{:?}", entry.version),
+                "status": entry.status.as_u16(),
+                "size": entry.size,
+                "peer": entry.peer,
+            });
+            info!(target: ACCESS_TARGET, "{}", line)
+        }
+    }
+}
+
+/// clf_time formats time as in the Common Log Format, e.g. "10/Oct/2000:13:55:36 +0000"
+fn clf_time(time: SystemTime) -> String {
+    // e.g. "Tue, 10 Oct 2000 13:55:36 GMT"
+    let date = HttpDate::from(time).to_string();
+    match date.split(' ').collect::<Vec<&str>>()[..] {
+        [_, day, month, year, time, _] => format!("{}/{}/{}:{} +0000", day, month, year, time),
+        _ => date,
+    }
+}
+
+pub struct LogSubscriber {
+    /// target prefix and level, None targets apply to everything
+    directives: Vec<(Option<String>, LevelFilter)>,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: String,
+    refs: usize,
+}
+
+thread_local! {
+    /// spans entered on the current thread, innermost last
+    static STACK: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// FieldWriter appends fields as " key=value", the message of an event is written as is
+struct FieldWriter<'a>(&'a mut String);
+
+impl Visit for FieldWriter<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            let _ = write!(self.0, " {}", value);
+        } else {
+            let _ = write!(self.0, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, " {:?}", value);
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+impl LogSubscriber {
+    /// from_env builds the subscriber from RUST_LOG, unparsable directives are ignored
+    pub fn from_env() -> LogSubscriber {
+        let filter = std::env::var("RUST_LOG").unwrap_or_default();
+        let mut directives = filter
+            .split(',')
+            .map(str::trim)
+            .filter(|directive| !directive.is_empty())
+            .filter_map(|directive| match directive.split_once('=') {
+                Some((target, level)) => Some((Some(target.to_string()), level.parse().ok()?)),
+                None => Some((None, directive.parse().ok()?)),
+            })
+            .collect::<Vec<(Option<String>, LevelFilter)>>();
+        if !directives.iter().any(|(target, _)| target.is_none()) {
+            directives.push((None, LevelFilter::INFO));
+        }
+        LogSubscriber {
+            directives,
+            next_id: AtomicU64::new(1),
+            spans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .filter(|(prefix, _)| {
+                prefix
+                    .as_ref()
+                    .is_none_or(|prefix| target.starts_with(prefix.as_str()))
+            })
+            .max_by_key(|(prefix, _)| prefix.as_ref().map_or(0, |prefix| prefix.len() + 1))
+            .map_or(LevelFilter::INFO, |(_, level)| *level)
+    }
+}
+
+impl Subscriber for LogSubscriber {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.directives.iter().map(|(_, level)| *level).max()
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut fields = String::new();
+        span.record(&mut FieldWriter(&mut fields));
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.spans
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                id,
+                SpanData {
+                    name: span.metadata().name(),
+                    fields,
+                    refs: 1,
+                },
+            );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut spans = self.spans.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(data) = spans.get_mut(&span.into_u64()) {
+            values.record(&mut FieldWriter(&mut data.fields));
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let metadata = event.metadata();
+        if metadata.target() == ACCESS_TARGET {
+            let mut line = String::new();
+            event.record(&mut FieldWriter(&mut line));
+            println!("{}", line.trim_start());
+            return;
+        }
+        let mut line = format!(
+            "{} {:>5} ",
+            crate::rfc3339(SystemTime::now()),
+            metadata.level()
+        );
+        {
+            let spans = self.spans.lock().unwrap_or_else(PoisonError::into_inner);
+            STACK.with(|stack| {
+                for data in stack.borrow().iter().filter_map(|id| spans.get(id)) {
+                    let _ = write!(line, "{}{{{}}}: ", data.name, data.fields.trim_start());
+                }
+            });
+        }
+        line.push_str(metadata.target());
+        line.push(':');
+        event.record(&mut FieldWriter(&mut line));
+        println!("{}", line);
+    }
+
+    fn enter(&self, span: &Id) {
+        STACK.with(|stack| stack.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if let Some(pos) = stack.iter().rposition(|id| *id == span.into_u64()) {
+                stack.remove(pos);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        let mut spans = self.spans.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(data) = spans.get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(data) = spans.get_mut(&span.into_u64()) else {
+            return false;
+        };
+        data.refs -= 1;
+        if data.refs == 0 {
+            spans.remove(&span.into_u64());
+            return true;
+        }
+        false
+    }
+}
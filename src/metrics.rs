@@ -0,0 +1,163 @@
+//! Process wide counters, exposed via /metrics in the Prometheus text exposition format. Gauges
+//! describing the store are computed from the store when rendering instead of being tracked.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use hyper::StatusCode;
+use serde::Serialize;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+static DOWNLOADS: AtomicU64 = AtomicU64::new(0);
+/// lookups of downloads, indexed by Lookup
+static LOOKUPS: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+/// answered requests keyed by method and status
+static REQUESTS: Mutex<BTreeMap<(&'static str, u16), u64>> = Mutex::new(BTreeMap::new());
+
+/// record_request counts a request answered with status
+pub fn record_request(method: &str, status: StatusCode) {
+    // methods are client controlled, only the known ones get a label of their own to keep the
+    // number of series bounded
+    let method = match method {
+        "GET" => "GET",
+        "HEAD" => "HEAD",
+        "POST" => "POST",
+        "PUT" => "PUT",
+        "DELETE" => "DELETE",
+        "OPTIONS" => "OPTIONS",
+        _ => "other",
+    };
+    let mut requests = REQUESTS.lock().unwrap_or_else(PoisonError::into_inner);
+    *requests.entry((method, status.as_u16())).or_default() += 1;
+}
+
+/// record_upload counts a file stored via an upload
+pub fn record_upload() {
+    UPLOADS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// record_download counts a file served with its content
+pub fn record_download() {
+    DOWNLOADS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Lookup is the outcome of looking up a file to download in the store
+#[derive(Clone, Copy)]
+pub enum Lookup {
+    /// found with its content in memory
+    Hit,
+    /// found, but too large to be cached so the content is read from the backend
+    Uncached,
+    /// not in the store, rare unless clients request files that never existed
+    Miss,
+}
+
+impl Lookup {
+    fn label(self) -> &'static str {
+        match self {
+            Lookup::Hit => "hit",
+            Lookup::Uncached => "uncached",
+            Lookup::Miss => "miss",
+        }
+    }
+}
+
+/// Lookups counts the outcomes of store lookups by downloads, as shown by /stats
+#[derive(Serialize)]
+pub struct Lookups {
+    pub hits: u64,
+    pub uncached: u64,
+    pub misses: u64,
+}
+
+/// record_lookup counts a download looking up a file
+pub fn record_lookup(lookup: Lookup) {
+    LOOKUPS[lookup as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// lookups returns the lookups counted so far
+pub fn lookups() -> Lookups {
+    let count = |lookup: Lookup| LOOKUPS[lookup as usize].load(Ordering::Relaxed);
+    Lookups {
+        hits: count(Lookup::Hit),
+        uncached: count(Lookup::Uncached),
+        misses: count(Lookup::Miss),
+    }
+}
+
+/// render formats every metric, along with the number of files and bytes currently stored
+pub fn render(files: usize, bytes: u64) -> String {
+    let mut out = String::new();
+    header(
+        &mut out,
+        "cdn_requests_total",
+        "counter",
+        "Requests answered.",
+    );
+    let requests = REQUESTS.lock().unwrap_or_else(PoisonError::into_inner);
+    for ((method, status), count) in requests.iter() {
+        let _ = writeln!(
+            out,
+            "cdn_requests_total{{method=\"{}\",status=\"{}\"}} {}",
+            method, status, count
+        );
+    }
+    drop(requests);
+    sample(
+        &mut out,
+        "cdn_uploads_total",
+        "counter",
+        "Files stored via uploads.",
+        UPLOADS.load(Ordering::Relaxed),
+    );
+    sample(
+        &mut out,
+        "cdn_downloads_total",
+        "counter",
+        "Files downloaded.",
+        DOWNLOADS.load(Ordering::Relaxed),
+    );
+    header(
+        &mut out,
+        "cdn_store_lookups_total",
+        "counter",
+        "Store lookups by downloads, by whether the file was found in memory.",
+    );
+    for lookup in [Lookup::Hit, Lookup::Uncached, Lookup::Miss] {
+        let _ = writeln!(
+            out,
+            "cdn_store_lookups_total{{result=\"{}\"}} {}",
+            lookup.label(),
+            LOOKUPS[lookup as usize].load(Ordering::Relaxed)
+        );
+    }
+    sample(
+        &mut out,
+        "cdn_files",
+        "gauge",
+        "Files in the store.",
+        files as u64,
+    );
+    sample(
+        &mut out,
+        "cdn_store_bytes",
+        "gauge",
+        "Sum of the sizes of all files in the store.",
+        bytes,
+    );
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+}
+
+/// sample writes a metric consisting of a single unlabeled sample
+fn sample(out: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    header(out, name, kind, help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
@@ -0,0 +1,116 @@
+//! Minimal MessagePack encoder. Values are serialized into a serde_json::Value first and encoded
+//! from there, which loses nothing for the response types of the cdn and spares implementing a
+//! serde Serializer.
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// to_vec serializes value as MessagePack
+pub fn to_vec<T: Serialize>(value: &T) -> serde_json::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    write(&mut out, &serde_json::to_value(value)?);
+    Ok(out)
+}
+
+fn write(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(false) => out.push(0xc2),
+        Value::Bool(true) => out.push(0xc3),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                write_uint(out, n);
+            } else if let Some(n) = n.as_i64() {
+                write_int(out, n);
+            } else {
+                out.push(0xcb);
+                out.extend(n.as_f64().unwrap_or_default().to_be_bytes());
+            }
+        }
+        Value::String(s) => write_str(out, s),
+        Value::Array(values) => {
+            write_len(out, values.len(), [0x90, 0xdc, 0xdd]);
+            for value in values {
+                write(out, value);
+            }
+        }
+        Value::Object(map) => {
+            write_len(out, map.len(), [0x80, 0xde, 0xdf]);
+            for (key, value) in map {
+                write_str(out, key);
+                write(out, value);
+            }
+        }
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    match s.len() {
+        len @ 0..=31 => out.push(0xa0 | len as u8),
+        len @ 32..=0xff => out.extend([0xd9, len as u8]),
+        len @ 0x100..=0xffff => {
+            out.push(0xda);
+            out.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(0xdb);
+            out.extend((len as u32).to_be_bytes());
+        }
+    }
+    out.extend(s.as_bytes());
+}
+
+/// write_len writes the header of an array or map of len entries, given the markers of its fixed,
+/// 16 and 32 bit forms
+fn write_len(out: &mut Vec<u8>, len: usize, [fix, m16, m32]: [u8; 3]) {
+    match len {
+        0..=15 => out.push(fix | len as u8),
+        16..=0xffff => {
+            out.push(m16);
+            out.extend((len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(m32);
+            out.extend((len as u32).to_be_bytes());
+        }
+    }
+}
+
+fn write_uint(out: &mut Vec<u8>, n: u64) {
+    match n {
+        0..=0x7f => out.push(n as u8),
+        0x80..=0xff => out.extend([0xcc, n as u8]),
+        0x100..=0xffff => {
+            out.push(0xcd);
+            out.extend((n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(0xce);
+            out.extend((n as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(0xcf);
+            out.extend(n.to_be_bytes());
+        }
+    }
+}
+
+/// write_int writes negative integers, positive ones are written by write_uint
+fn write_int(out: &mut Vec<u8>, n: i64) {
+    match n {
+        -32..=-1 => out.push(n as u8),
+        -0x80..=-33 => out.extend([0xd0, n as u8]),
+        -0x8000..=-0x81 => {
+            out.push(0xd1);
+            out.extend((n as i16).to_be_bytes());
+        }
+        -0x8000_0000..=-0x8001 => {
+            out.push(0xd2);
+            out.extend((n as i32).to_be_bytes());
+        }
+        _ => {
+            out.push(0xd3);
+            out.extend(n.to_be_bytes());
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Streaming multipart/form-data (RFC 7578) parser. Parts are read one after another straight
+//! from the request body, only the bytes that could belong to a delimiter spanning two frames are
+//! held back, so part contents are never buffered as a whole.
+
+use std::pin::Pin;
+
+use anyhow::Result;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes};
+
+/// headers of a part larger than this are rejected instead of being buffered
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/// Malformed is returned for bodies not following the multipart syntax
+#[derive(Debug)]
+pub struct Malformed(&'static str);
+
+impl std::fmt::Display for Malformed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "malformed multipart body: {}", self.0)
+    }
+}
+
+impl std::error::Error for Malformed {}
+
+/// Part holds the Content-Disposition filename of a part, parts without one are plain form fields
+pub struct Part {
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+pub struct Multipart<B> {
+    body: Pin<Box<B>>,
+    buf: Vec<u8>,
+    /// "\r\n--boundary", the CRLF belongs to the delimiter and not to the preceding content
+    delimiter: Vec<u8>,
+    started: bool,
+    in_part: bool,
+    done: bool,
+    /// bytes read from body so far, bodies exceeding limit fail with PayloadTooLarge
+    read: u64,
+    limit: u64,
+}
+
+impl<B> Multipart<B>
+where
+    B: Body<Data = Bytes>,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    pub fn new(body: B, boundary: &str, limit: u64) -> Multipart<B> {
+        Multipart {
+            body: Box::pin(body),
+            // the first delimiter has no preceding CRLF, pretend it had one
+            buf: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            started: false,
+            in_part: false,
+            done: false,
+            read: 0,
+            limit,
+        }
+    }
+
+    /// fill appends the next frame of the body to buf, false if the body ended
+    async fn fill(&mut self) -> Result<bool> {
+        while let Some(frame) = self.body.frame().await {
+            let Ok(chunk) = frame?.into_data() else {
+                continue;
+            };
+            self.read += chunk.len() as u64;
+            if self.read > self.limit {
+                return Err(crate::PayloadTooLarge.into());
+            }
+            self.buf.extend_from_slice(&chunk);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// next_part skips whatever is left of the current part and returns the headers of the next
+    /// one, None once the closing delimiter was read
+    pub async fn next_part(&mut self) -> Result<Option<Part>> {
+        while self.next_chunk().await?.is_some() {}
+        if self.done {
+            return Ok(None);
+        }
+
+        if !self.started {
+            // everything before the first delimiter is a preamble to be ignored
+            loop {
+                if let Some(pos) = find(&self.buf, &self.delimiter) {
+                    self.buf.drain(..pos + self.delimiter.len());
+                    break;
+                }
+                let keep = self.buf.len().min(self.delimiter.len() - 1);
+                self.buf.drain(..self.buf.len() - keep);
+                if !self.fill().await? {
+                    return Err(Malformed("missing boundary").into());
+                }
+            }
+            self.started = true;
+        }
+
+        while self.buf.len() < 2 {
+            if !self.fill().await? {
+                return Err(Malformed("unexpected end of body").into());
+            }
+        }
+        if self.buf.starts_with(b"--") {
+            self.done = true;
+            return Ok(None);
+        }
+
+        // the CRLF terminating the delimiter line doubles as the start of the header block
+        let end = loop {
+            if let Some(pos) = find(&self.buf, b"\r\n\r\n") {
+                break pos;
+            }
+            if self.buf.len() > MAX_HEADER_SIZE {
+                return Err(Malformed("part headers too large").into());
+            }
+            if !self.fill().await? {
+                return Err(Malformed("unexpected end of part headers").into());
+            }
+        };
+        let headers = String::from_utf8_lossy(&self.buf[..end]).into_owned();
+        self.buf.drain(..end + 4);
+        self.in_part = true;
+
+        let header = |key: &str| {
+            headers
+                .split("\r\n")
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.trim())
+        };
+        let mut part = Part {
+            filename: None,
+            content_type: header("content-type").map(String::from),
+        };
+        let disposition = header("content-disposition");
+        for param in disposition.unwrap_or_default().split(';').skip(1) {
+            let Some((key, value)) = param.split_once('=') else {
+                continue;
+            };
+            if key.trim().eq_ignore_ascii_case("filename") {
+                part.filename = Some(value.trim().trim_matches('"').to_string());
+            }
+        }
+        Ok(Some(part))
+    }
+
+    /// next_chunk returns the next piece of content of the current part, None at its end
+    pub async fn next_chunk(&mut self) -> Result<Option<Bytes>> {
+        while self.in_part {
+            if let Some(pos) = find(&self.buf, &self.delimiter) {
+                let chunk = self.buf.drain(..pos).collect::<Vec<u8>>();
+                self.buf.drain(..self.delimiter.len());
+                self.in_part = false;
+                if chunk.is_empty() {
+                    return Ok(None);
+                }
+                return Ok(Some(chunk.into()));
+            }
+            // the tail could be the start of a delimiter split across frames
+            let keep = self.delimiter.len() - 1;
+            if self.buf.len() > keep {
+                let chunk = self.buf.drain(..self.buf.len() - keep).collect::<Vec<u8>>();
+                return Ok(Some(chunk.into()));
+            }
+            if !self.fill().await? {
+                return Err(Malformed("unexpected end of part").into());
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// boundary extracts the boundary parameter from a multipart/form-data Content-Type, None for
+/// any other type
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
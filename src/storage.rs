@@ -0,0 +1,379 @@
+//! Backends persisting the content of files. The FileStore only ever holds what is persisted via
+//! the backend, LocalDisk is used unless Config::storage is set.
+//!
+//! Backends only need to implement the required methods. Renames default to copying, dedup is
+//! skipped and the expiries and content types of files don't survive restarts unless a backend
+//! also implements rename, link and the metadata methods.
+
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use hyper::body::Bytes;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::warn;
+
+use crate::{RESERVED_PREFIX, TEMP_PREFIX};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Reader yields the content of a file as returned by StorageBackend::open
+pub type Reader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// Entry is a file held by a backend, as listed by StorageBackend::list
+pub struct Entry {
+    /// key of the file, e.g. "css/app.css"
+    pub key: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// StorageBackend persists file content by key. Keys are already sanitized when passed in, they
+/// never contain "." or ".." segments and never start with the reserved ".cdn-" prefix
+pub trait StorageBackend: Send + Sync {
+    /// recover is called once before the store is loaded and no write is in progress, so
+    /// leftovers of writes interrupted by a crash can be cleaned up
+    fn recover(&self) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// list returns every file held by the backend. Files that can't be listed are skipped, only
+    /// failing to list anything at all is an error
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<Entry>>>;
+
+    /// read returns the content of key
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Bytes>>;
+
+    /// open returns a reader over the content of key starting at offset, so large files can be
+    /// sent without holding them in memory. Defaults to slicing what read returns
+    fn open<'a>(&'a self, key: &'a str, offset: u64) -> BoxFuture<'a, io::Result<Reader>> {
+        Box::pin(async move {
+            let content = self.read(key).await?;
+            let offset = content
+                .len()
+                .min(usize::try_from(offset).unwrap_or(usize::MAX));
+            Ok(Box::pin(io::Cursor::new(content.slice(offset..))) as Reader)
+        })
+    }
+
+    /// write starts writing the content of key, which is only replaced once StorageWriter::finish
+    /// succeeds. Dropping the writer before discards whatever was written
+    fn write<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Box<dyn StorageWriter>>>;
+
+    /// delete removes key, a missing key is reported as io::ErrorKind::NotFound
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>>;
+
+    /// exists reports whether key is held by the backend
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<bool>>;
+
+    /// rename moves the content of from to the key to, replacing it. Defaults to copying the
+    /// content via open and write before deleting from, which unlike LocalDisk isn't atomic
+    fn rename<'a>(&'a self, from: &'a str, to: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let mut content = self.open(from, 0).await?;
+            let mut writer = self.write(to).await?;
+            let mut chunk = vec![0; 64 * 1024];
+            loop {
+                let read = content.read(&mut chunk).await?;
+                if read == 0 {
+                    break;
+                }
+                writer.write(&chunk[..read]).await?;
+            }
+            writer.finish().await?;
+            self.delete(from).await
+        })
+    }
+
+    /// link replaces to with the content of from without storing it twice, see Config::dedup,
+    /// and returns the modification time of to. Defaults to failing with
+    /// io::ErrorKind::Unsupported, both keys then keep a copy of their own
+    fn link<'a>(&'a self, _from: &'a str, _to: &'a str) -> BoxFuture<'a, io::Result<SystemTime>> {
+        Box::pin(async { Err(io::ErrorKind::Unsupported.into()) })
+    }
+
+    /// read_metadata returns the internal document name as last written by write_metadata, None
+    /// if it was never written. Names start with the reserved ".cdn-" prefix, so they never
+    /// collide with keys. Defaults to None
+    fn read_metadata<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, io::Result<Option<Bytes>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// write_metadata atomically replaces the internal document name, e.g. the expiries of files.
+    /// Defaults to failing with io::ErrorKind::Unsupported, the metadata is then lost on restart
+    fn write_metadata<'a>(
+        &'a self,
+        _name: &'a str,
+        _content: Bytes,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async { Err(io::ErrorKind::Unsupported.into()) })
+    }
+}
+
+/// StorageWriter receives the content of a single file as written by StorageBackend::write
+pub trait StorageWriter: Send {
+    fn write<'a>(&'a mut self, chunk: &'a [u8]) -> BoxFuture<'a, io::Result<()>>;
+
+    /// finish makes the content available under its key at once and returns its modification time
+    fn finish(self: Box<Self>) -> BoxFuture<'static, io::Result<SystemTime>>;
+}
+
+/// LocalDisk stores every file below a directory, keys are paths relative to it
+pub struct LocalDisk {
+    dir: PathBuf,
+}
+
+impl LocalDisk {
+    pub fn new(dir: impl Into<PathBuf>) -> LocalDisk {
+        LocalDisk { dir: dir.into() }
+    }
+
+    /// walk lists the files in the directory and its subdirectories, see StorageBackend::list.
+    /// Internal files are never listed, with remove_temp temporary files of uploads are deleted,
+    /// which is only safe while no upload can be in progress
+    async fn walk(&self, remove_temp: bool) -> io::Result<Vec<Entry>> {
+        let mut files = Vec::new();
+        // directories still to be read, along with their key prefix
+        let mut dirs = vec![(self.dir.clone(), String::new())];
+        while let Some((dir, prefix)) = dirs.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(err) if prefix.is_empty() => return Err(err),
+                Err(err) => {
+                    warn!(path = %dir.display(), "skipping unreadable directory: {}", err);
+                    continue;
+                }
+            };
+            loop {
+                let entry = match entries.next_entry().await {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => break,
+                    Err(err) => {
+                        warn!(path = %dir.display(), "failed to list directory: {}", err);
+                        break;
+                    }
+                };
+                // keys have to be valid utf8 to be requested at all
+                let Some(name) = entry.file_name().to_str().map(String::from) else {
+                    warn!(path = %entry.path().display(), "skipping file with non utf8 name");
+                    continue;
+                };
+                let key = format!("{}{}", prefix, name);
+                let file_type = match entry.file_type().await {
+                    Ok(file_type) => file_type,
+                    Err(err) => {
+                        warn!(path = %entry.path().display(), "skipping unreadable file: {}", err);
+                        continue;
+                    }
+                };
+                // symlinked directories aren't followed, they could form a cycle
+                if file_type.is_dir() {
+                    dirs.push((entry.path(), format!("{}/", key)));
+                    continue;
+                }
+                if name.starts_with(RESERVED_PREFIX) {
+                    if remove_temp && name.starts_with(TEMP_PREFIX) {
+                        let _ = fs::remove_file(entry.path()).await;
+                    }
+                    continue;
+                }
+                let metadata = async {
+                    let metadata = fs::metadata(entry.path()).await?;
+                    io::Result::Ok((metadata.is_dir(), metadata.len(), metadata.modified()?))
+                };
+                match metadata.await {
+                    Ok((true, _, _)) => {}
+                    Ok((false, size, modified)) => files.push(Entry {
+                        key,
+                        size,
+                        modified,
+                    }),
+                    Err(err) => {
+                        warn!(path = %entry.path().display(), "skipping unreadable file: {}", err);
+                    }
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+impl StorageBackend for LocalDisk {
+    fn recover(&self) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(async move {
+            fs::create_dir_all(&self.dir).await?;
+            self.walk(true).await.map(drop)
+        })
+    }
+
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<Entry>>> {
+        Box::pin(self.walk(false))
+    }
+
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Bytes>> {
+        Box::pin(async move { fs::read(self.dir.join(key)).await.map(Bytes::from) })
+    }
+
+    fn open<'a>(&'a self, key: &'a str, offset: u64) -> BoxFuture<'a, io::Result<Reader>> {
+        Box::pin(async move {
+            let mut file = fs::File::open(self.dir.join(key)).await?;
+            file.seek(io::SeekFrom::Start(offset)).await?;
+            Ok(Box::pin(file) as Reader)
+        })
+    }
+
+    fn write<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Box<dyn StorageWriter>>> {
+        Box::pin(async move {
+            let temp = temp_path(&self.dir);
+            let dest = self.dir.join(key);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            let out = fs::File::create(&temp).await?;
+            Ok(Box::new(LocalWriter {
+                out: Some((out, temp)),
+                dest,
+            }) as Box<dyn StorageWriter>)
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let path = self.dir.join(key);
+            fs::remove_file(&path).await?;
+            remove_empty_parents(&self.dir, &path).await;
+            Ok(())
+        })
+    }
+
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<bool>> {
+        Box::pin(fs::try_exists(self.dir.join(key)))
+    }
+
+    fn rename<'a>(&'a self, from: &'a str, to: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let source = self.dir.join(from);
+            let dest = self.dir.join(to);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::rename(&source, &dest).await?;
+            remove_empty_parents(&self.dir, &source).await;
+            Ok(())
+        })
+    }
+
+    /// link hard links from to a temporary file that is renamed to to, so to is replaced at once
+    fn link<'a>(&'a self, from: &'a str, to: &'a str) -> BoxFuture<'a, io::Result<SystemTime>> {
+        Box::pin(async move {
+            let temp = temp_path(&self.dir);
+            let dest = self.dir.join(to);
+            let linked = async {
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                fs::hard_link(self.dir.join(from), &temp).await?;
+                fs::rename(&temp, &dest).await
+            };
+            if let Err(err) = linked.await {
+                let _ = fs::remove_file(&temp).await;
+                return Err(err);
+            }
+            // hard links share their metadata, so this is the modification time of from
+            fs::metadata(&dest).await?.modified()
+        })
+    }
+
+    fn read_metadata<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Option<Bytes>>> {
+        Box::pin(async move {
+            match fs::read(self.dir.join(name)).await {
+                Ok(content) => Ok(Some(content.into())),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err),
+            }
+        })
+    }
+
+    fn write_metadata<'a>(
+        &'a self,
+        name: &'a str,
+        content: Bytes,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let temp = temp_path(&self.dir);
+            let written = async {
+                fs::write(&temp, &content).await?;
+                fs::rename(&temp, self.dir.join(name)).await
+            };
+            if let Err(err) = written.await {
+                let _ = fs::remove_file(&temp).await;
+                return Err(err);
+            }
+            Ok(())
+        })
+    }
+}
+
+/// LocalWriter writes to a temporary file that is only renamed to dest once complete, so neither
+/// readers nor a restart after a crash ever get to see a partially written file
+struct LocalWriter {
+    out: Option<(fs::File, PathBuf)>,
+    dest: PathBuf,
+}
+
+impl StorageWriter for LocalWriter {
+    fn write<'a>(&'a mut self, chunk: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            match &mut self.out {
+                Some((out, _)) => out.write_all(chunk).await,
+                None => Err(io::Error::other("write after finish")),
+            }
+        })
+    }
+
+    fn finish(mut self: Box<Self>) -> BoxFuture<'static, io::Result<SystemTime>> {
+        Box::pin(async move {
+            let Some((out, temp)) = &mut self.out else {
+                return Err(io::Error::other("finished twice"));
+            };
+            out.sync_all().await?;
+            let modified = out.metadata().await?.modified()?;
+            fs::rename(&temp, &self.dest).await?;
+            self.out = None;
+            Ok(modified)
+        })
+    }
+}
+
+impl Drop for LocalWriter {
+    /// drop removes the temporary file of writes that failed or were abandoned
+    fn drop(&mut self) {
+        if let Some((_, temp)) = self.out.take() {
+            let _ = std::fs::remove_file(temp);
+        }
+    }
+}
+
+/// temp_path returns a new unique path for a temporary file in dir
+fn temp_path(dir: &Path) -> PathBuf {
+    static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+    dir.join(format!(
+        "{}{}",
+        TEMP_PREFIX,
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// remove_empty_parents removes the directories between path and dir that are left empty
+async fn remove_empty_parents(dir: &Path, path: &Path) {
+    for parent in path.ancestors().skip(1) {
+        // removing a directory fails if it still has entries, which is where to stop
+        if parent == dir || !parent.starts_with(dir) || fs::remove_dir(parent).await.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Minimal ustar (POSIX.1-1988) writer, only regular files are supported. Names not fitting the
+//! 100 byte name field and sizes of 8 GiB and more are stored via a preceding pax extended header
+//! (POSIX.1-2001).
+
+const BLOCK_SIZE: usize = 512;
+
+/// END marks the end of an archive, two zeroed blocks
+pub const END: [u8; 2 * BLOCK_SIZE] = [0; 2 * BLOCK_SIZE];
+
+/// MAX_SIZE is the largest size fitting the 11 octal digits of the size field, about 8 GiB
+const MAX_SIZE: u64 = 0o77777777777;
+
+/// header builds the header block(s) preceding the content of a regular file with the given
+/// size and modification time in seconds since the unix epoch
+pub fn header(name: &str, size: u64, mtime: u64) -> Vec<u8> {
+    let mut records = String::new();
+    if name.len() > 100 {
+        records.push_str(&pax_record("path", name));
+    }
+    if size > MAX_SIZE {
+        records.push_str(&pax_record("size", &size.to_string()));
+    }
+    if records.is_empty() {
+        return block(name.as_bytes(), b'0', size, mtime).to_vec();
+    }
+
+    let mut out = block(b"././@PaxHeader", b'x', records.len() as u64, mtime).to_vec();
+    out.extend(records.as_bytes());
+    out.resize(out.len() + padding(records.len() as u64), 0);
+    // the plain header still carries a truncated name for readers not supporting pax, sizes too
+    // large for it are only found in the size record
+    let mut end = name.len().min(100);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    let truncated = &name.as_bytes()[..end];
+    let size = if size > MAX_SIZE { 0 } else { size };
+    out.extend(block(truncated, b'0', size, mtime));
+    out
+}
+
+/// pax_record formats a record of a pax extended header, "<len> <key>=<value>\n" where len counts
+/// the whole record including itself
+fn pax_record(key: &str, value: &str) -> String {
+    let record_len = |digits: usize| digits + " =\n".len() + key.len() + value.len();
+    let mut digits = 1;
+    while record_len(digits).to_string().len() > digits {
+        digits += 1;
+    }
+    format!("{} {}={}\n", record_len(digits), key, value)
+}
+
+/// padding is the number of zero bytes required after size bytes of content to fill the block
+pub fn padding(size: u64) -> usize {
+    (BLOCK_SIZE - (size % BLOCK_SIZE as u64) as usize) % BLOCK_SIZE
+}
+
+fn block(name: &[u8], kind: u8, size: u64, mtime: u64) -> [u8; BLOCK_SIZE] {
+    let mut block = [0u8; BLOCK_SIZE];
+    block[..name.len()].copy_from_slice(name);
+    octal(&mut block[100..108], 0o644);
+    octal(&mut block[108..116], 0);
+    octal(&mut block[116..124], 0);
+    octal(&mut block[124..136], size);
+    octal(&mut block[136..148], mtime);
+    block[156] = kind;
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+
+    // the checksum is computed with its own field filled with spaces
+    block[148..156].fill(b' ');
+    let checksum = block.iter().map(|b| *b as u64).sum::<u64>();
+    octal(&mut block[148..155], checksum);
+    block
+}
+
+/// octal writes value zero padded and nul terminated into field
+fn octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{:0width$o}", value, width = width);
+    field[..width].copy_from_slice(&digits.as_bytes()[digits.len() - width..]);
+    field[width] = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_beyond_the_size_field() {
+        assert_eq!(header("a.txt", MAX_SIZE, 0).len(), BLOCK_SIZE);
+
+        let size = 8 << 30;
+        let out = header("a.txt", size, 0);
+        assert_eq!(out.len(), 3 * BLOCK_SIZE);
+        assert_eq!(out[156], b'x');
+        let record = "19 size=8589934592\n";
+        assert_eq!(
+            &out[BLOCK_SIZE..BLOCK_SIZE + record.len()],
+            record.as_bytes()
+        );
+        let plain = &out[2 * BLOCK_SIZE..];
+        assert_eq!(&plain[..6], b"a.txt\0");
+        assert_eq!(plain[156], b'0');
+        assert_eq!(&plain[124..136], b"00000000000\0");
+    }
+
+    #[test]
+    fn long_names_and_large_sizes() {
+        let name = "d/".repeat(60) + "a.txt";
+        let out = header(&name, 8 << 30, 0);
+        let records = format!("{}{}", pax_record("path", &name), "19 size=8589934592\n");
+        assert_eq!(
+            &out[BLOCK_SIZE..BLOCK_SIZE + records.len()],
+            records.as_bytes()
+        );
+        assert_eq!(
+            &out[124..136],
+            format!("{:011o}\0", records.len()).as_bytes()
+        );
+    }
+}
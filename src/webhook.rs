@@ -0,0 +1,83 @@
+//! Upload notifications, POSTed as json to Config::webhook_url once a file is stored. Only plain
+//! http urls are supported, there is no TLS stack to reach https endpoints with.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{CONTENT_TYPE, HOST};
+use hyper::{Request, Uri};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use tokio::net::TcpStream;
+use tracing::warn;
+
+/// TIMEOUT bounds connecting, sending a notification and waiting for the response to it
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Upload is the payload sent for every stored file
+#[derive(Serialize)]
+pub struct Upload<'a> {
+    pub name: &'a str,
+    pub size: u64,
+    pub sha256: &'a str,
+}
+
+/// parse_url parses url, which has to be an absolute http url
+pub fn parse_url(url: &str) -> Result<Uri> {
+    let uri = url.parse::<Uri>()?;
+    if uri.scheme_str() != Some("http") || uri.host().is_none() {
+        anyhow::bail!("expected an absolute http url, e.g. http://localhost:9000/hook");
+    }
+    Ok(uri)
+}
+
+/// notify POSTs upload to url in the background without waiting for the response, failures are
+/// only logged
+pub fn notify(url: Uri, upload: &Upload) {
+    let body = match serde_json::to_vec(upload) {
+        Ok(body) => body,
+        Err(err) => {
+            warn!(file = %upload.name, "Failed to encode webhook payload: {}", err);
+            return;
+        }
+    };
+    let name = upload.name.to_string();
+    tokio::spawn(async move {
+        match tokio::time::timeout(TIMEOUT, send(&url, body)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => warn!(file = %name, "Failed to notify webhook: {:#}", err),
+            Err(_) => warn!(
+                file = %name,
+                "Webhook didn't answer within {}s",
+                TIMEOUT.as_secs()
+            ),
+        }
+    });
+}
+
+async fn send(url: &Uri, body: Vec<u8>) -> Result<()> {
+    let authority = url.authority().context("webhook url has no host")?;
+    // ipv6 hosts keep the brackets of their url form
+    let host = authority
+        .host()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let stream = TcpStream::connect((host, authority.port_u16().unwrap_or(80)))
+        .await
+        .with_context(|| format!("Failed to connect to {}", authority))?;
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    // drives the connection until the response is read and sender is dropped
+    tokio::spawn(conn);
+
+    let req = Request::post(url.path_and_query().map_or("/", |path| path.as_str()))
+        .header(HOST, authority.as_str())
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body)))?;
+    let res = sender.send_request(req).await?;
+    if !res.status().is_success() {
+        anyhow::bail!("webhook answered with {}", res.status());
+    }
+    Ok(())
+}
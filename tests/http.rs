@@ -0,0 +1,2392 @@
+//! End to end tests, every test serves its own store directory on an ephemeral port and talks to
+//! it over http.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use cdn::client::{ApiError, CdnClient};
+use cdn::storage::{BoxFuture, Entry, Reader, StorageBackend, StorageWriter};
+use cdn::{init_store, serve, signed_url, Config, ErrorCode};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::http::request;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{oneshot, Semaphore};
+
+/// Server shuts down and removes its store directory once dropped
+struct Server {
+    addr: SocketAddr,
+    store_dir: PathBuf,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        let _ = std::fs::remove_dir_all(&self.store_dir);
+    }
+}
+
+async fn start() -> Server {
+    start_with(Config::default()).await
+}
+
+/// start_with serves config, with store_dir replaced by a fresh temporary directory, once the
+/// store is loaded
+async fn start_with(config: Config) -> Server {
+    wait_ready(spawn(config).await).await
+}
+
+/// wait_ready polls /ready until server loaded its store, requests to anything but the probes
+/// fail until then
+async fn wait_ready(server: Server) -> Server {
+    while request(&server, Method::GET, "/ready", "").await.0 != StatusCode::OK {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    server
+}
+
+/// spawn serves config like start_with, without waiting for the store to be loaded
+async fn spawn(config: Config) -> Server {
+    static SERVERS: AtomicU64 = AtomicU64::new(0);
+    let store_dir = std::env::temp_dir().join(format!(
+        "cdn-test-{}-{}",
+        std::process::id(),
+        SERVERS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let config = Arc::new(Config {
+        store_dir: store_dir.clone(),
+        ..config
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (shutdown, signal) = oneshot::channel::<()>();
+    tokio::spawn(serve(listener, config, async {
+        let _ = signal.await;
+    }));
+    Server {
+        addr,
+        store_dir,
+        shutdown: Some(shutdown),
+    }
+}
+
+/// request sends a single request on a fresh connection and collects the response body
+async fn request(server: &Server, method: Method, path: &str, body: &str) -> (StatusCode, Bytes) {
+    let res = send(server, builder(server, method, path), body).await;
+    (res.status(), res.into_body())
+}
+
+/// builder starts a request to path on server, to be sent with send
+fn builder(server: &Server, method: Method, path: &str) -> request::Builder {
+    Request::builder()
+        .method(method)
+        .uri(path)
+        .header("host", server.addr.to_string())
+}
+
+/// send completes req with body and sends it on a fresh connection, collecting the response
+async fn send(server: &Server, req: request::Builder, body: &str) -> Response<Bytes> {
+    let stream = TcpStream::connect(server.addr).await.unwrap();
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+    let req = req.body(Full::new(Bytes::from(body.to_string()))).unwrap();
+    let (parts, body) = sender.send_request(req).await.unwrap().into_parts();
+    Response::from_parts(parts, body.collect().await.unwrap().to_bytes())
+}
+
+fn json(body: &Bytes) -> Value {
+    serde_json::from_slice(body).unwrap()
+}
+
+#[tokio::test]
+async fn upload_and_download() {
+    let server = start().await;
+    let (status, body) = request(&server, Method::POST, "/file/hello.txt", "hello world").await;
+    assert_eq!(status, StatusCode::CREATED);
+    let body = json(&body);
+    assert_eq!(body["msg"], "Stored file 'hello.txt'");
+    assert!(body.get("code").is_none());
+    assert!(server.store_dir.join("hello.txt").exists());
+
+    let (status, body) = request(&server, Method::GET, "/file/hello.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "hello world");
+}
+
+#[tokio::test]
+async fn upload_existing_conflicts() {
+    let server = start().await;
+    let (status, _) = request(&server, Method::POST, "/file/a.txt", "a").await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, body) = request(&server, Method::POST, "/file/a.txt", "b").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(json(&body)["code"], "conflict");
+}
+
+#[tokio::test]
+async fn list_files() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/b.txt", "bb").await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+
+    let (status, body) = request(&server, Method::GET, "/files", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let body = json(&body);
+    assert_eq!(body["total"], 2);
+    let files = body["files"].as_array().unwrap();
+    assert_eq!(files[0]["name"], "a.txt");
+    assert_eq!(files[0]["size"], 1);
+    assert_eq!(files[1]["name"], "b.txt");
+    assert_eq!(files[1]["size"], 2);
+    assert!(files[0].get("content").is_none());
+}
+
+#[tokio::test]
+async fn list_files_pagination() {
+    let server = start().await;
+    for name in ["c.txt", "a.txt", "e.txt", "b.txt", "d.txt"] {
+        request(&server, Method::POST, &format!("/file/{}", name), "x").await;
+    }
+
+    for (query, expected) in [
+        ("", &["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"][..]),
+        ("?limit=2", &["a.txt", "b.txt"]),
+        ("?limit=2&offset=2", &["c.txt", "d.txt"]),
+        ("?limit=2&offset=4", &["e.txt"]),
+        ("?offset=10", &[]),
+    ] {
+        let (status, body) = request(&server, Method::GET, &format!("/files{}", query), "").await;
+        assert_eq!(status, StatusCode::OK, "{}", query);
+        assert_eq!(listed_names(&body), expected, "{}", query);
+        // total counts the files on every page
+        assert_eq!(json(&body)["total"], 5, "{}", query);
+    }
+
+    for query in ["?limit=0", "?limit=-1", "?offset=x"] {
+        let (status, body) = request(&server, Method::GET, &format!("/files{}", query), "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+        assert_eq!(json(&body)["code"], "bad_request");
+    }
+}
+
+#[tokio::test]
+async fn list_files_prefix_and_sort() {
+    let server = start().await;
+    for (name, content) in [
+        ("css/b.css", "bbb"),
+        ("css/a.css", "aaaa"),
+        ("js/c.js", "c"),
+        ("d.txt", "dd"),
+        ("e.txt", "ee"),
+    ] {
+        request(&server, Method::POST, &format!("/file/{}", name), content).await;
+    }
+
+    for (query, expected, total) in [
+        ("?prefix=css/", &["css/a.css", "css/b.css"][..], 2),
+        ("?prefix=css/&limit=1&offset=1", &["css/b.css"], 2),
+        ("?prefix=nothing", &[], 0),
+        // ties in size are ordered by name
+        (
+            "?sort=size",
+            &["js/c.js", "d.txt", "e.txt", "css/b.css", "css/a.css"],
+            5,
+        ),
+        ("?sort=name&prefix=js", &["js/c.js"], 1),
+        ("?sort=size&prefix=css/&limit=1", &["css/b.css"], 2),
+    ] {
+        let (status, body) = request(&server, Method::GET, &format!("/files{}", query), "").await;
+        assert_eq!(status, StatusCode::OK, "{}", query);
+        assert_eq!(listed_names(&body), expected, "{}", query);
+        assert_eq!(json(&body)["total"], total, "{}", query);
+    }
+
+    let (status, body) = request(&server, Method::GET, "/files?sort=date", "").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json(&body)["code"], "bad_request");
+}
+
+/// listed_names returns the names of the files of a /files response
+fn listed_names(body: &Bytes) -> Vec<String> {
+    json(body)["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| file["name"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn download_missing_file() {
+    let server = start().await;
+    let (status, body) = request(&server, Method::GET, "/file/missing.txt", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let body = json(&body);
+    assert_eq!(body["msg"], "File 'missing.txt' not found in store");
+    assert_eq!(body["code"], "not_found");
+
+    let (status, _) = request(&server, Method::GET, "/unknown", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn path_traversal_is_rejected() {
+    let server = start().await;
+    for path in [
+        "/file/../Cargo.toml",
+        "/file/css/../../Cargo.toml",
+        "/file/..\\Cargo.toml",
+    ] {
+        let (status, _) = request(&server, Method::GET, path, "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", path);
+    }
+
+    let (status, body) = request(&server, Method::POST, "/file/../escaped.txt", "x").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(json(&body)["code"], "bad_request");
+    assert!(!server
+        .store_dir
+        .parent()
+        .unwrap()
+        .join("escaped.txt")
+        .exists());
+
+    // names sent as form fields can't escape the store either
+    for name in ["../escaped.txt", "css/../../escaped.txt", "..", ".", ""] {
+        let body = format!("name={}&content=x", name);
+        let (status, _) = request(&server, Method::POST, "/file", &body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", name);
+    }
+    assert!(!server
+        .store_dir
+        .parent()
+        .unwrap()
+        .join("escaped.txt")
+        .exists());
+    let (_, body) = request(&server, Method::GET, "/files", "").await;
+    assert_eq!(json(&body)["files"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn checksum() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/hello.txt", "hello world").await;
+    let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    let (status, body) = request(&server, Method::GET, "/file/hello.txt/checksum", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, sha256);
+
+    let (_, body) = request(&server, Method::GET, "/files", "").await;
+    assert_eq!(json(&body)["files"][0]["sha256"], sha256);
+}
+
+#[tokio::test]
+async fn rename() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    request(&server, Method::POST, "/file/b.txt", "b").await;
+
+    let (status, _) = request(&server, Method::POST, "/file/a.txt/rename?to=css/c.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!server.store_dir.join("a.txt").exists());
+    assert!(server.store_dir.join("css/c.txt").exists());
+    let (status, body) = request(&server, Method::GET, "/file/css/c.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "a");
+
+    let (status, _) = request(&server, Method::POST, "/file/a.txt/rename?to=d.txt", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = request(&server, Method::POST, "/file/b.txt/rename?to=css/c.txt", "").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = request(&server, Method::POST, "/file/b.txt/rename?to=../b.txt", "").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // forgetting to says so instead of uploading a file named rename
+    let (status, body) = request(&server, Method::POST, "/file/b.txt/rename", "").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(json(&body)["msg"]
+        .as_str()
+        .unwrap()
+        .contains("missing ?to="));
+    let (_, body) = request(&server, Method::GET, "/files?fields=name", "").await;
+    assert_eq!(
+        json(&body)["names"],
+        serde_json::json!(["b.txt", "css/c.txt"])
+    );
+    // rename is a name like any other below names not in the store
+    let (status, _) = request(&server, Method::POST, "/file/docs/rename", "r").await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn popular() {
+    let server = start().await;
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        request(&server, Method::POST, &format!("/file/{}", name), name).await;
+    }
+    for path in ["/file/b.txt", "/file/b.txt", "/file/c.txt"] {
+        request(&server, Method::GET, path, "").await;
+    }
+    request(&server, Method::HEAD, "/file/a.txt", "").await;
+
+    let (status, body) = request(&server, Method::GET, "/files/popular?limit=2", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let body = json(&body);
+    let files = body["files"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[0]["name"], "b.txt");
+    assert_eq!(files[0]["downloads"], 2);
+    assert_eq!(files[1]["name"], "c.txt");
+    assert_eq!(files[1]["downloads"], 1);
+}
+
+/// MemoryBackend keeps the content of files in a map, standing in for a remote backend
+#[derive(Clone, Default)]
+struct MemoryBackend(Arc<Mutex<HashMap<String, Bytes>>>);
+
+struct MemoryWriter {
+    backend: MemoryBackend,
+    key: String,
+    content: Vec<u8>,
+}
+
+impl StorageBackend for MemoryBackend {
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<Entry>>> {
+        let entries = self
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, content)| Entry {
+                key: key.clone(),
+                size: content.len() as u64,
+                modified: SystemTime::UNIX_EPOCH,
+            })
+            .collect();
+        Box::pin(async { Ok(entries) })
+    }
+
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Bytes>> {
+        let content = self.0.lock().unwrap().get(key).cloned();
+        Box::pin(async { content.ok_or_else(|| io::ErrorKind::NotFound.into()) })
+    }
+
+    fn write<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Box<dyn StorageWriter>>> {
+        let writer = MemoryWriter {
+            backend: self.clone(),
+            key: key.to_string(),
+            content: Vec::new(),
+        };
+        Box::pin(async { Ok(Box::new(writer) as Box<dyn StorageWriter>) })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        let removed = self.0.lock().unwrap().remove(key);
+        Box::pin(async {
+            removed
+                .map(drop)
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        })
+    }
+
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<bool>> {
+        let exists = self.0.lock().unwrap().contains_key(key);
+        Box::pin(async move { Ok(exists) })
+    }
+}
+
+impl StorageWriter for MemoryWriter {
+    fn write<'a>(&'a mut self, chunk: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        self.content.extend_from_slice(chunk);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn finish(self: Box<Self>) -> BoxFuture<'static, io::Result<SystemTime>> {
+        let mut files = self.backend.0.lock().unwrap();
+        files.insert(self.key.clone(), self.content.into());
+        Box::pin(async { Ok(SystemTime::now()) })
+    }
+}
+
+#[tokio::test]
+async fn custom_storage() {
+    let backend = MemoryBackend::default();
+    backend
+        .0
+        .lock()
+        .unwrap()
+        .insert(String::from("preloaded.txt"), Bytes::from("preloaded"));
+    let server = start_with(Config {
+        storage: Some(Arc::new(backend.clone())),
+        // keeps nothing in memory, so every download goes through the backend
+        cache_max_size: 0,
+        ..Config::default()
+    })
+    .await;
+
+    let (status, body) = request(&server, Method::GET, "/file/preloaded.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "preloaded");
+
+    let (status, _) = request(&server, Method::POST, "/file/css/app.css", "body {}").await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(backend.0.lock().unwrap()["css/app.css"], "body {}");
+    assert!(!server.store_dir.join("css/app.css").exists());
+    let (_, body) = request(&server, Method::GET, "/file/css/app.css", "").await;
+    assert_eq!(body, "body {}");
+
+    let (status, _) = request(&server, Method::DELETE, "/file/css/app.css", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(backend.0.lock().unwrap().get("css/app.css").is_none());
+}
+
+#[tokio::test]
+async fn custom_storage_features() {
+    let backend = MemoryBackend::default();
+    let server = start_with(Config {
+        storage: Some(Arc::new(backend.clone())),
+        cache_max_size: 0,
+        dedup: true,
+        ..Config::default()
+    })
+    .await;
+    request(&server, Method::POST, "/file/a.txt?ttl=3600", "same").await;
+    // backends without link keep a copy of their own
+    let (status, _) = request(&server, Method::POST, "/file/b.txt", "same").await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // renames copy the content by default
+    let (status, _) = request(&server, Method::POST, "/file/b.txt/rename?to=c/d.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    {
+        let files = backend.0.lock().unwrap();
+        assert_eq!(files["a.txt"], "same");
+        assert_eq!(files["c/d.txt"], "same");
+        assert!(files.get("b.txt").is_none());
+    }
+    let (_, body) = request(&server, Method::GET, "/file/c/d.txt", "").await;
+    assert_eq!(body, "same");
+
+    // files not held in memory are archived from the backend
+    let (status, body) = request(&server, Method::GET, "/files/archive", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.len(), 4 * 512 + 1024);
+    assert_eq!(&body[..5], b"a.txt");
+    assert_eq!(&body[512..516], b"same");
+    assert_eq!(&body[1024..1031], b"c/d.txt");
+
+    // nothing bypasses the backend, not even the expiry of a.txt
+    assert!(!server.store_dir.exists());
+}
+
+#[tokio::test]
+async fn ttl() {
+    let server = start().await;
+    let (status, _) = request(&server, Method::POST, "/file/short.txt?ttl=1", "short").await;
+    assert_eq!(status, StatusCode::CREATED);
+    request(&server, Method::POST, "/file/long.txt?ttl=3600", "long").await;
+    request(&server, Method::POST, "/file/kept.txt", "kept").await;
+    for ttl in ["0", "-1", "soon"] {
+        let path = format!("/file/bad.txt?ttl={}", ttl);
+        let (status, _) = request(&server, Method::POST, &path, "bad").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", ttl);
+    }
+
+    let (status, _) = request(&server, Method::GET, "/file/short.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = request(&server, Method::GET, "/files?sort=name", "").await;
+    let files = json(&body)["files"].clone();
+    assert!(files[0]["expires"].is_null(), "{}", files);
+    assert!(files[1]["expires"].is_string(), "{}", files);
+    assert!(files[2]["expires"].is_string(), "{}", files);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let (status, _) = request(&server, Method::GET, "/file/short.txt", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    for path in ["/file/long.txt", "/file/kept.txt"] {
+        let (status, _) = request(&server, Method::GET, path, "").await;
+        assert_eq!(status, StatusCode::OK, "{}", path);
+    }
+
+    // expiries survive restarts
+    let store = init_store(&Config {
+        store_dir: server.store_dir.clone(),
+        ..Config::default()
+    })
+    .await
+    .unwrap();
+    let store = store.read().unwrap();
+    let in_an_hour = SystemTime::now() + Duration::from_secs(3600);
+    assert!(store["long.txt"]
+        .expires
+        .is_some_and(|expires| expires <= in_an_hour));
+    assert!(store["kept.txt"].expires.is_none());
+}
+
+#[tokio::test]
+async fn dedup() {
+    use std::os::unix::fs::MetadataExt;
+
+    let server = start_with(Config {
+        dedup: true,
+        // downloads read the shared content from disk
+        cache_max_size: 0,
+        ..Config::default()
+    })
+    .await;
+    for (name, content) in [("a.txt", "same"), ("b.txt", "same"), ("c.txt", "other")] {
+        let path = format!("/file/{}", name);
+        let (status, _) = request(&server, Method::POST, &path, content).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+    let inode = |name| {
+        std::fs::metadata(server.store_dir.join(name))
+            .unwrap()
+            .ino()
+    };
+    assert_eq!(inode("a.txt"), inode("b.txt"));
+    assert_ne!(inode("a.txt"), inode("c.txt"));
+    assert_eq!(
+        std::fs::metadata(server.store_dir.join("a.txt"))
+            .unwrap()
+            .nlink(),
+        2
+    );
+
+    // replacing one of the names leaves the other untouched
+    let (status, _) = request(&server, Method::PUT, "/file/b.txt", "changed").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(inode("a.txt"), inode("b.txt"));
+    let (_, body) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(body, "same");
+
+    // and so does deleting it
+    request(&server, Method::POST, "/file/d.txt", "same").await;
+    assert_eq!(inode("a.txt"), inode("d.txt"));
+    let (status, _) = request(&server, Method::DELETE, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = request(&server, Method::GET, "/file/d.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "same");
+}
+
+#[tokio::test]
+async fn evicts_least_recently_used() {
+    let server = start_with(Config {
+        max_store_size: Some(10),
+        ..Config::default()
+    })
+    .await;
+    let pause = || tokio::time::sleep(Duration::from_millis(5));
+    request(&server, Method::POST, "/file/a.txt", "aaaa").await;
+    pause().await;
+    request(&server, Method::POST, "/file/b.txt", "bbbb").await;
+    pause().await;
+    request(&server, Method::GET, "/file/a.txt", "").await;
+    pause().await;
+
+    let (status, _) = request(&server, Method::POST, "/file/c.txt", "cccc").await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = request(&server, Method::GET, "/file/b.txt", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(!server.store_dir.join("b.txt").exists());
+    let (status, _) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = request(&server, Method::POST, "/file/d.txt", "larger than 10").await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn concurrent_uploads_stay_within_store_size() {
+    let server = Arc::new(
+        start_with(Config {
+            max_store_size: Some(10),
+            ..Config::default()
+        })
+        .await,
+    );
+    let uploads = (0..20)
+        .map(|i| {
+            let server = Arc::clone(&server);
+            tokio::spawn(async move {
+                let path = format!("/file/{}.txt", i);
+                request(&server, Method::POST, &path, "xxx").await.0
+            })
+        })
+        .collect::<Vec<_>>();
+    for upload in uploads {
+        assert_eq!(upload.await.unwrap(), StatusCode::CREATED);
+    }
+
+    let (_, body) = request(&server, Method::GET, "/files", "").await;
+    let mut names = listed_names(&body);
+    names.sort();
+    assert_eq!(names.len(), 3);
+    let mut stored = std::fs::read_dir(&server.store_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".txt"))
+        .collect::<Vec<_>>();
+    stored.sort();
+    assert_eq!(stored, names);
+}
+
+#[tokio::test]
+async fn invalid_names_are_rejected() {
+    let server = start().await;
+    let long = format!("name={}&content=x", "a".repeat(2000));
+    for body in [
+        "name=&content=x",
+        "name=%20%20&content=x",
+        "name=a%00b&content=x",
+        "name=a%0Ab&content=x",
+        "name=css/%20/app.css&content=x",
+        &long,
+    ] {
+        let (status, body) = request(&server, Method::POST, "/file", body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json(&body)["code"], "bad_request");
+    }
+
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    let (status, _) = request(&server, Method::POST, "/file/a.txt/rename?to=%09", "").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn server_header() {
+    let server = start().await;
+    for path in ["/health", "/file/missing.txt"] {
+        let res = send(&server, builder(&server, Method::GET, path), "").await;
+        assert_eq!(
+            res.headers()["server"],
+            concat!("rust_cdn/", env!("CARGO_PKG_VERSION"))
+        );
+    }
+
+    let server = start_with(Config {
+        server_name: None,
+        ..Config::default()
+    })
+    .await;
+    let res = send(&server, builder(&server, Method::GET, "/health"), "").await;
+    assert!(!res.headers().contains_key("server"));
+}
+
+#[tokio::test]
+async fn if_match() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/a.txt", "one").await;
+    let res = send(&server, builder(&server, Method::GET, "/file/a.txt"), "").await;
+    let etag = res.headers()["etag"].to_str().unwrap().to_string();
+
+    let put = |etag: &str| builder(&server, Method::PUT, "/file/a.txt").header("if-match", etag);
+    let res = send(&server, put(&etag), "two").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    // the etag changed along with the content
+    let res = send(&server, put(&etag), "three").await;
+    assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(json(res.body())["code"], "precondition_failed");
+    let (_, body) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(body, "two");
+
+    let req = builder(&server, Method::POST, "/file/b.txt?overwrite=true").header("if-match", "*");
+    let res = send(&server, req, "b").await;
+    assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+}
+
+/// GatedBackend holds no files, listing them only finishes once the gate is opened
+struct GatedBackend(Arc<Semaphore>);
+
+impl StorageBackend for GatedBackend {
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<Entry>>> {
+        Box::pin(async {
+            let _ = self.0.acquire().await;
+            Ok(Vec::new())
+        })
+    }
+
+    fn read<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<Bytes>> {
+        Box::pin(async { Err(io::ErrorKind::NotFound.into()) })
+    }
+
+    fn write<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<Box<dyn StorageWriter>>> {
+        Box::pin(async { Err(io::ErrorKind::Unsupported.into()) })
+    }
+
+    fn delete<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async { Err(io::ErrorKind::NotFound.into()) })
+    }
+
+    fn exists<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<bool>> {
+        Box::pin(async { Ok(false) })
+    }
+}
+
+#[tokio::test]
+async fn request_ids() {
+    let server = start().await;
+    let mut ids = Vec::new();
+    for path in ["/files", "/file/missing.txt"] {
+        let res = send(&server, builder(&server, Method::GET, path), "").await;
+        let id = res.headers()["x-request-id"].to_str().unwrap().to_string();
+        assert!(!id.is_empty());
+        ids.push(id);
+    }
+    assert_ne!(ids[0], ids[1]);
+
+    let req = builder(&server, Method::GET, "/files").header("x-request-id", "trace-42");
+    let res = send(&server, req, "").await;
+    assert_eq!(res.headers()["x-request-id"], "trace-42");
+
+    // ids that are too long or not printable ascii are replaced rather than echoed
+    for invalid in ["has space".to_string(), "x".repeat(129)] {
+        let req = builder(&server, Method::GET, "/files").header("x-request-id", &invalid);
+        let res = send(&server, req, "").await;
+        let id = res.headers()["x-request-id"].to_str().unwrap();
+        assert!(!id.is_empty());
+        assert_ne!(id, invalid);
+    }
+}
+
+#[tokio::test]
+async fn cache_max_age() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    let res = send(&server, builder(&server, Method::GET, "/file/a.txt"), "").await;
+    assert_eq!(res.headers()["cache-control"], "public, max-age=3600");
+
+    // no other test reads the environment, so setting it can't race with them
+    for (max_age, expected) in [("60", "public, max-age=60"), ("no-store", "no-store")] {
+        std::env::set_var("CDN_CACHE_MAX_AGE", max_age);
+        let config = Config::from_env();
+        std::env::remove_var("CDN_CACHE_MAX_AGE");
+        let server = start_with(config.unwrap()).await;
+        request(&server, Method::POST, "/file/a.txt", "a").await;
+
+        let res = send(&server, builder(&server, Method::GET, "/file/a.txt"), "").await;
+        assert_eq!(res.headers()["cache-control"], expected);
+        let etag = res.headers()["etag"].clone();
+        let req = builder(&server, Method::GET, "/file/a.txt").header("if-none-match", etag);
+        let res = send(&server, req, "").await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers()["cache-control"], expected);
+    }
+
+    std::env::set_var("CDN_CACHE_MAX_AGE", "soon");
+    let config = Config::from_env();
+    std::env::remove_var("CDN_CACHE_MAX_AGE");
+    assert!(config.is_err());
+}
+
+#[tokio::test]
+async fn if_none_match() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    let res = send(&server, builder(&server, Method::GET, "/file/a.txt"), "").await;
+    let etag = res.headers()["etag"].to_str().unwrap().to_string();
+    // the quoted sha256 of the content, so it's the same across restarts and builds
+    assert_eq!(
+        etag,
+        "\"ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb\""
+    );
+
+    let matching = [
+        etag.clone(),
+        format!("W/{}", etag),
+        format!("\"other\", {}", etag),
+        String::from("*"),
+    ];
+    for method in [Method::GET, Method::HEAD] {
+        for value in &matching {
+            let req =
+                builder(&server, method.clone(), "/file/a.txt").header("if-none-match", value);
+            let res = send(&server, req, "").await;
+            assert_eq!(
+                res.status(),
+                StatusCode::NOT_MODIFIED,
+                "{} {}",
+                method,
+                value
+            );
+            assert!(res.body().is_empty());
+            assert_eq!(res.headers()["etag"], etag.as_str());
+        }
+    }
+    let req = builder(&server, Method::GET, "/file/a.txt").header("if-none-match", "\"other\"");
+    let res = send(&server, req, "").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.body(), "a");
+
+    // replacing the content changes the etag
+    request(&server, Method::PUT, "/file/a.txt", "b").await;
+    let req = builder(&server, Method::GET, "/file/a.txt").header("if-none-match", &etag);
+    let res = send(&server, req, "").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.body(), "b");
+    assert_ne!(res.headers()["etag"], etag.as_str());
+}
+
+#[tokio::test]
+async fn if_modified_since() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    let res = send(&server, builder(&server, Method::GET, "/file/a.txt"), "").await;
+    let last_modified = res.headers()["last-modified"].to_str().unwrap().to_string();
+    let modified = httpdate::parse_http_date(&last_modified).unwrap();
+    // within a second of the upload, dates only have second granularity
+    assert!(SystemTime::now().duration_since(modified).unwrap() < Duration::from_secs(2));
+
+    let since = |since| {
+        let req = builder(&server, Method::GET, "/file/a.txt").header("if-modified-since", since);
+        send(&server, req, "")
+    };
+    for date in [
+        last_modified.clone(),
+        httpdate::fmt_http_date(modified + Duration::from_secs(60)),
+    ] {
+        let res = since(date.clone()).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED, "{}", date);
+        assert!(res.body().is_empty());
+        assert_eq!(res.headers()["last-modified"], last_modified.as_str());
+    }
+    for date in [
+        httpdate::fmt_http_date(modified - Duration::from_secs(1)),
+        String::from("yesterday"),
+    ] {
+        let res = since(date.clone()).await;
+        assert_eq!(res.status(), StatusCode::OK, "{}", date);
+        assert_eq!(res.body(), "a");
+    }
+
+    // If-None-Match takes precedence
+    let req = builder(&server, Method::GET, "/file/a.txt")
+        .header("if-modified-since", &last_modified)
+        .header("if-none-match", "\"other\"");
+    assert_eq!(send(&server, req, "").await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn ready_once_loaded() {
+    let gate = Arc::new(Semaphore::new(0));
+    let server = spawn(Config {
+        storage: Some(Arc::new(GatedBackend(Arc::clone(&gate)))),
+        ..Config::default()
+    })
+    .await;
+
+    let (status, _) = request(&server, Method::GET, "/health", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = request(&server, Method::GET, "/ready", "").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(json(&body)["code"], "service_unavailable");
+    let (status, _) = request(&server, Method::GET, "/files", "").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    gate.add_permits(1);
+    let server = wait_ready(server).await;
+    let (status, _) = request(&server, Method::GET, "/files", "").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn metrics() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/a.txt", "abc").await;
+    request(&server, Method::GET, "/file/a.txt", "").await;
+    request(&server, Method::GET, "/file/missing.txt", "").await;
+
+    let res = send(&server, builder(&server, Method::GET, "/metrics"), "").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let body = std::str::from_utf8(res.body()).unwrap();
+    // counters are process wide and shared with the other tests, the gauges are per store
+    let value = |name: &str| {
+        body.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap()
+    };
+    assert!(value("cdn_uploads_total") >= 1);
+    assert!(value("cdn_downloads_total") >= 1);
+    assert!(value("cdn_requests_total{method=\"POST\",status=\"201\"}") >= 1);
+    assert!(value("cdn_store_lookups_total{result=\"hit\"}") >= 1);
+    assert!(value("cdn_store_lookups_total{result=\"miss\"}") >= 1);
+    assert_eq!(value("cdn_files"), 1);
+    assert_eq!(value("cdn_store_bytes"), 3);
+    assert!(body.contains("# TYPE cdn_requests_total counter\n"));
+
+    let (_, body) = request(&server, Method::GET, "/stats", "").await;
+    let body = json(&body);
+    assert_eq!(body["files"], 1);
+    assert!(body["lookups"]["hits"].as_u64().unwrap() >= 1);
+    assert!(body["lookups"]["misses"].as_u64().unwrap() >= 1);
+}
+
+#[tokio::test]
+async fn trailing_slash() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    let (status, body) = request(&server, Method::GET, "/files/", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json(&body)["files"][0]["name"], "a.txt");
+    let (status, body) = request(&server, Method::GET, "/file/a.txt/", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "a");
+}
+
+#[tokio::test]
+async fn index() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/css/app.css", "body {}").await;
+    request(&server, Method::POST, "/file/a&b.txt", "b").await;
+    request(&server, Method::POST, "/file", "name=my+file.txt&content=c").await;
+
+    let res = send(&server, builder(&server, Method::GET, "/"), "").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let body = std::str::from_utf8(res.body()).unwrap();
+    assert!(body.contains("<a href=\"/file/css/app.css\">css/app.css</a>"));
+    assert!(body.contains("<a href=\"/file/a%26b.txt\">a&amp;b.txt</a>"));
+    assert!(body.contains("<a href=\"/file/my%20file.txt\">my file.txt</a>"));
+    // the links lead to the files
+    let (status, body) = request(&server, Method::GET, "/file/my%20file.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "c");
+}
+
+#[tokio::test]
+async fn max_file_size() {
+    let server = start_with(Config {
+        max_file_size: Some(4),
+        ..Config::default()
+    })
+    .await;
+    let (status, body) = request(&server, Method::POST, "/file/big.txt", "12345").await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(json(&body)["msg"].as_str().unwrap().contains("big.txt"));
+    let (status, _) = request(&server, Method::POST, "/file/small.txt", "1234").await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // every part is bounded on its own, while the body as a whole may be larger
+    let body = "--b\r\nContent-Disposition: form-data; name=\"a\"; filename=\"a.txt\"\r\n\r\n1234\r\n\
+                --b\r\nContent-Disposition: form-data; name=\"b\"; filename=\"b.txt\"\r\n\r\n12345\r\n\
+                --b--\r\n";
+    let req = builder(&server, Method::POST, "/file")
+        .header("content-type", "multipart/form-data; boundary=b");
+    let res = send(&server, req, body).await;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let (status, _) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = request(&server, Method::GET, "/file/b.txt", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn ranged_head() {
+    // uncached files are served from the backend, cached ones from memory
+    for cache_max_size in [0, 1024] {
+        let server = start_with(Config {
+            cache_max_size,
+            ..Config::default()
+        })
+        .await;
+        request(&server, Method::POST, "/file/a.txt", "0123456789").await;
+
+        let ranged = |method| builder(&server, method, "/file/a.txt").header("range", "bytes=2-5");
+        let head = send(&server, ranged(Method::HEAD), "").await;
+        let get = send(&server, ranged(Method::GET), "").await;
+        assert_eq!(head.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(get.body(), "2345");
+        assert!(head.body().is_empty());
+        for header in ["content-range", "content-length", "etag"] {
+            assert_eq!(head.headers()[header], get.headers()[header], "{}", header);
+        }
+        assert_eq!(head.headers()["content-range"], "bytes 2-5/10");
+        assert_eq!(head.headers()["content-length"], "4");
+
+        let head = send(&server, builder(&server, Method::HEAD, "/file/a.txt"), "").await;
+        assert_eq!(head.status(), StatusCode::OK);
+        assert_eq!(head.headers()["content-length"], "10");
+        assert_eq!(head.headers()["accept-ranges"], "bytes");
+    }
+}
+
+#[tokio::test]
+async fn multiple_ranges() {
+    for cache_max_size in [0, 1024] {
+        let server = start_with(Config {
+            cache_max_size,
+            ..Config::default()
+        })
+        .await;
+        request(&server, Method::POST, "/file/a.txt", "0123456789").await;
+        let (_, body) = request(&server, Method::GET, "/files", "").await;
+        let boundary = format!(
+            "cdn-{}",
+            json(&body)["files"][0]["sha256"].as_str().unwrap()
+        );
+
+        // unsatisfiable ranges are left out
+        let ranged = |method| {
+            builder(&server, method, "/file/a.txt").header("range", "bytes=0-1, 20-30, -3")
+        };
+        let res = send(&server, ranged(Method::GET), "").await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers()["content-type"],
+            format!("multipart/byteranges; boundary={}", boundary).as_str()
+        );
+        let expected = format!(
+            "\r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 7-9/10\r\n\r\n789\
+             \r\n--{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(res.body(), expected.as_str());
+        assert_eq!(res.headers()["content-length"], expected.len().to_string());
+        assert!(res.headers().get("content-range").is_none());
+
+        let head = send(&server, ranged(Method::HEAD), "").await;
+        assert_eq!(head.headers()["content-length"], expected.len().to_string());
+        assert!(head.body().is_empty());
+
+        let ranges = vec!["0-0"; 17].join(",");
+        let req = builder(&server, Method::GET, "/file/a.txt")
+            .header("range", format!("bytes={}", ranges));
+        let res = send(&server, req, "").await;
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    }
+}
+
+#[tokio::test]
+async fn idle_connections_are_closed() {
+    let server = start_with(Config {
+        idle_timeout: Some(Duration::from_millis(100)),
+        ..Config::default()
+    })
+    .await;
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    // trickling in a byte at a time keeps the connection open, once nothing arrives it is closed
+    for byte in b"GET /hea" {
+        stream.write_all(&[*byte]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(40)).await;
+    }
+    let mut buf = Vec::new();
+    let read = tokio::time::timeout(Duration::from_secs(2), stream.read_to_end(&mut buf)).await;
+    assert!(read.is_ok(), "connection wasn't closed");
+    assert!(buf.is_empty());
+}
+
+#[tokio::test]
+async fn max_connections() {
+    let server = start_with(Config {
+        max_connections: Some(1),
+        ..Config::default()
+    })
+    .await;
+    let first = TcpStream::connect(server.addr).await.unwrap();
+    // waits in the listen backlog until the first connection closes
+    let second = request(&server, Method::GET, "/health", "");
+    let mut second = pin!(second);
+    let waited = tokio::time::timeout(Duration::from_millis(200), &mut second).await;
+    assert!(waited.is_err(), "second connection was served");
+
+    drop(first);
+    let (status, _) = tokio::time::timeout(Duration::from_secs(2), second)
+        .await
+        .unwrap();
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn content_type() {
+    let server = start().await;
+    let req = builder(&server, Method::POST, "/file/report").header("content-type", "text/csv");
+    assert_eq!(
+        send(&server, req, "a,b").await.status(),
+        StatusCode::CREATED
+    );
+    let (status, _) = request(
+        &server,
+        Method::POST,
+        "/file/page?content_type=text/html",
+        "<p>",
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = request(&server, Method::POST, "/file/x?content_type=bogus", "").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    for (name, expected) in [("report", "text/csv"), ("page", "text/html")] {
+        let res = send(
+            &server,
+            builder(&server, Method::GET, &format!("/file/{}", name)),
+            "",
+        )
+        .await;
+        assert_eq!(res.headers()["content-type"], expected);
+    }
+
+    // whether files are displayed inline follows the type they are served with, not their name
+    let req = builder(&server, Method::POST, "/file/blob").header("content-type", "image/png");
+    send(&server, req, "png").await;
+    let req =
+        builder(&server, Method::POST, "/file/a.png").header("content-type", "application/zip");
+    send(&server, req, "zip").await;
+    for (name, expected) in [
+        ("blob", "inline"),
+        ("a.png", "attachment"),
+        ("page", "inline"),
+    ] {
+        let res = send(
+            &server,
+            builder(&server, Method::GET, &format!("/file/{}", name)),
+            "",
+        )
+        .await;
+        let disposition = res.headers()["content-disposition"].to_str().unwrap();
+        assert!(
+            disposition.starts_with(expected),
+            "{}: {}",
+            name,
+            disposition
+        );
+    }
+    request(&server, Method::DELETE, "/file/blob", "").await;
+    request(&server, Method::DELETE, "/file/a.png", "").await;
+
+    let (_, body) = request(&server, Method::GET, "/files", "").await;
+    let body = json(&body);
+    let files = body["files"].as_array().unwrap();
+    assert_eq!(files[0]["name"], "page");
+    assert_eq!(files[0]["content_type"], "text/html");
+    assert_eq!(files[1]["content_type"], "text/csv");
+}
+
+#[tokio::test]
+async fn base64_download() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/logo.png", "\u{1}png").await;
+
+    let (status, body) = request(&server, Method::GET, "/file/logo.png?encoding=base64", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let body = json(&body);
+    assert_eq!(body["name"], "logo.png");
+    assert_eq!(body["content_type"], "image/png");
+    assert_eq!(body["content"], "AXBuZw==");
+
+    let (status, _) = request(&server, Method::GET, "/file/logo.png?encoding=hex", "").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = request(&server, Method::GET, "/file/missing?encoding=base64", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // files larger than what could be uploaded are only served raw
+    let backend = MemoryBackend::default();
+    backend
+        .0
+        .lock()
+        .unwrap()
+        .insert(String::from("large.bin"), Bytes::from("0123456789"));
+    let server = start_with(Config {
+        storage: Some(Arc::new(backend)),
+        max_file_size: Some(4),
+        cache_max_size: 0,
+        ..Config::default()
+    })
+    .await;
+    let (status, body) = request(&server, Method::GET, "/file/large.bin?encoding=base64", "").await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(json(&body)["code"], "payload_too_large");
+    let (status, body) = request(&server, Method::GET, "/file/large.bin", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "0123456789");
+}
+
+#[tokio::test]
+async fn store_dir_is_a_file() {
+    let path = std::env::temp_dir().join(format!("cdn-test-{}-file", std::process::id()));
+    std::fs::write(&path, "").unwrap();
+    let config = Config {
+        store_dir: path.clone(),
+        ..Config::default()
+    };
+    let err = init_store(&config).await.err().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(format!("{:#}", err).contains("is not a directory"));
+}
+
+/// FullBackend holds a single file a.txt and fails every write as if the disk was full
+struct FullBackend;
+
+impl StorageBackend for FullBackend {
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<Entry>>> {
+        Box::pin(async {
+            Ok(vec![Entry {
+                key: String::from("a.txt"),
+                size: 3,
+                modified: SystemTime::UNIX_EPOCH,
+            }])
+        })
+    }
+
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Bytes>> {
+        Box::pin(async move {
+            match key {
+                "a.txt" => Ok(Bytes::from("old")),
+                _ => Err(io::ErrorKind::NotFound.into()),
+            }
+        })
+    }
+
+    fn write<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<Box<dyn StorageWriter>>> {
+        Box::pin(async { Err(io::ErrorKind::StorageFull.into()) })
+    }
+
+    fn delete<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async { Err(io::ErrorKind::NotFound.into()) })
+    }
+
+    fn exists<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<bool>> {
+        Box::pin(async { Ok(false) })
+    }
+}
+
+#[tokio::test]
+async fn storage_full() {
+    let server = start_with(Config {
+        storage: Some(Arc::new(FullBackend)),
+        ..Config::default()
+    })
+    .await;
+
+    let (status, body) = request(&server, Method::POST, "/file/b.txt", "b").await;
+    assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+    assert_eq!(json(&body)["code"], "insufficient_storage");
+    let (status, _) = request(&server, Method::GET, "/file/b.txt", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn failed_writes_leave_the_store_unchanged() {
+    let server = start_with(Config {
+        storage: Some(Arc::new(FullBackend)),
+        ..Config::default()
+    })
+    .await;
+
+    let (status, _) = request(&server, Method::PUT, "/file/a.txt", "new").await;
+    assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+    let (status, body) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "old");
+    let (_, body) = request(&server, Method::GET, "/files", "").await;
+    assert_eq!(json(&body)["total"], 1);
+}
+
+#[tokio::test]
+async fn exists() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/css/app.css", "body {}").await;
+
+    for (path, exists) in [
+        ("/file/css/app.css/exists", true),
+        ("/file/missing.css/exists", false),
+        ("/file/../secret/exists", false),
+    ] {
+        let (status, body) = request(&server, Method::GET, path, "").await;
+        assert_eq!(status, StatusCode::OK, "{}", path);
+        assert_eq!(json(&body)["exists"], exists, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn streams_uncached_files() {
+    let server = start_with(Config {
+        cache_max_size: 0,
+        ..Config::default()
+    })
+    .await;
+    // spans several chunks read from disk
+    let content = (0..200_000)
+        .map(|i| (b'a' + (i % 26) as u8) as char)
+        .collect::<String>();
+    request(&server, Method::POST, "/file/large.txt", &content).await;
+
+    let (status, body) = request(&server, Method::GET, "/file/large.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, content);
+
+    let req = builder(&server, Method::GET, "/file/large.txt").header("range", "bytes=70000-");
+    let res = send(&server, req, "").await;
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.body(), &content[70_000..]);
+}
+
+#[tokio::test]
+async fn options() {
+    let server = start().await;
+    for (path, allow) in [
+        ("/file/a.txt", "GET, HEAD, POST, PUT, DELETE"),
+        ("/file", "POST, PUT"),
+        ("/files", "GET, DELETE"),
+        ("/health", "GET"),
+    ] {
+        let res = send(&server, builder(&server, Method::OPTIONS, path), "").await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT, "{}", path);
+        assert_eq!(res.headers()["allow"], allow, "{}", path);
+    }
+
+    let res = send(&server, builder(&server, Method::OPTIONS, "/unknown"), "").await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn cors() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    let from = |method, path, origin| builder(&server, method, path).header("origin", origin);
+
+    let res = send(
+        &server,
+        from(Method::GET, "/file/a.txt", "https://a.test"),
+        "",
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["access-control-allow-origin"], "*");
+    assert!(res.headers()["access-control-expose-headers"]
+        .to_str()
+        .unwrap()
+        .contains("etag"));
+    // only requests naming an origin get CORS headers
+    let res = send(&server, builder(&server, Method::GET, "/file/a.txt"), "").await;
+    assert!(res.headers().get("access-control-allow-origin").is_none());
+
+    let preflight = from(Method::OPTIONS, "/file/a.txt", "https://a.test")
+        .header("access-control-request-method", "PUT")
+        .header("access-control-request-headers", "x-custom, authorization");
+    let res = send(&server, preflight, "").await;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert!(res.body().is_empty());
+    assert_eq!(res.headers()["access-control-allow-origin"], "*");
+    assert_eq!(
+        res.headers()["access-control-allow-methods"],
+        "GET, HEAD, POST, PUT, DELETE"
+    );
+    assert_eq!(
+        res.headers()["access-control-allow-headers"],
+        "x-custom, authorization"
+    );
+    assert_eq!(res.headers()["access-control-max-age"], "86400");
+    let preflight = from(Method::OPTIONS, "/files", "https://a.test")
+        .header("access-control-request-method", "GET");
+    let res = send(&server, preflight, "").await;
+    assert_eq!(res.headers()["access-control-allow-methods"], "GET, DELETE");
+    assert!(res.headers()["access-control-allow-headers"]
+        .to_str()
+        .unwrap()
+        .contains("authorization"));
+
+    // listed origins are echoed back, others get no CORS headers at all
+    let server = start_with(Config {
+        cors_origins: vec![
+            String::from("https://a.test"),
+            String::from("https://b.test"),
+        ],
+        ..Config::default()
+    })
+    .await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    let from = |method, path, origin| builder(&server, method, path).header("origin", origin);
+    let res = send(
+        &server,
+        from(Method::GET, "/file/a.txt", "https://b.test"),
+        "",
+    )
+    .await;
+    assert_eq!(
+        res.headers()["access-control-allow-origin"],
+        "https://b.test"
+    );
+    assert!(res
+        .headers()
+        .get_all("vary")
+        .iter()
+        .any(|vary| vary == "origin"));
+    let res = send(
+        &server,
+        from(Method::GET, "/file/a.txt", "https://c.test"),
+        "",
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get("access-control-allow-origin").is_none());
+    assert!(res.headers().get("access-control-expose-headers").is_none());
+    let preflight = from(Method::OPTIONS, "/file/a.txt", "https://c.test")
+        .header("access-control-request-method", "DELETE");
+    let res = send(&server, preflight, "").await;
+    assert!(res.headers().get("access-control-allow-origin").is_none());
+}
+
+#[tokio::test]
+async fn list_routes() {
+    let server = start().await;
+    let (status, body) = request(&server, Method::GET, "/unknown", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(json(&body).get("routes").is_none());
+
+    let server = start_with(Config {
+        list_routes: true,
+        ..Config::default()
+    })
+    .await;
+    let (status, body) = request(&server, Method::GET, "/unknown", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let body = json(&body);
+    assert_eq!(body["code"], "not_found");
+    let routes = body["routes"].as_array().unwrap();
+    let files = routes
+        .iter()
+        .find(|route| route["path"] == "/files")
+        .unwrap();
+    assert_eq!(files["methods"], serde_json::json!(["GET", "DELETE"]));
+}
+
+#[tokio::test]
+async fn reload() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/old.txt", "old").await;
+    request(&server, Method::POST, "/file/gone.txt", "gone").await;
+    std::fs::write(server.store_dir.join("new.txt"), "new").unwrap();
+    std::fs::write(server.store_dir.join("old.txt"), "changed").unwrap();
+    std::fs::remove_file(server.store_dir.join("gone.txt")).unwrap();
+
+    let (status, body) = request(&server, Method::POST, "/admin/reload", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        json(&body)["msg"],
+        "Reloaded store, added 1 new, 1 changed and 1 removed File(s)"
+    );
+    let (_, body) = request(&server, Method::GET, "/file/new.txt", "").await;
+    assert_eq!(body, "new");
+    let (_, body) = request(&server, Method::GET, "/file/old.txt", "").await;
+    assert_eq!(body, "changed");
+    let (status, _) = request(&server, Method::GET, "/admin/reload", "").await;
+    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[tokio::test]
+async fn case_insensitive() {
+    let server = start_with(Config {
+        case_insensitive: true,
+        ..Config::default()
+    })
+    .await;
+    request(&server, Method::POST, "/file/img/Logo.png", "logo").await;
+
+    let res = send(
+        &server,
+        builder(&server, Method::GET, "/file/IMG/logo.PNG"),
+        "",
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.body(), "logo");
+    assert!(res.headers()["content-disposition"]
+        .to_str()
+        .unwrap()
+        .contains("Logo.png"));
+
+    let (_, body) = request(&server, Method::GET, "/file/IMG/LOGO.png/exists", "").await;
+    assert_eq!(json(&body)["exists"], true);
+    let (status, body) = request(&server, Method::GET, "/file/img/logo.png/checksum", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        "3598ce6f965b2481fe26316c06b30950c46ac7f8e7229f104aa78f579997668d"
+    );
+
+    let (status, _) = request(&server, Method::PUT, "/file/img/logo.png", "other").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = request(&server, Method::PUT, "/file/img/Logo.png", "new").await;
+    assert_eq!(status, StatusCode::OK);
+
+    // names are case sensitive by default
+    let server = start().await;
+    request(&server, Method::POST, "/file/Logo.png", "logo").await;
+    let (status, _) = request(&server, Method::GET, "/file/logo.png", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn signed_urls() {
+    let server = start_with(Config {
+        signing_secret: Some(String::from("secret")),
+        ..Config::default()
+    })
+    .await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+
+    // expires 2100-01-01, the signature is as computed by an independent HMAC-SHA256
+    let url = signed_url(
+        "secret",
+        "a.txt",
+        UNIX_EPOCH + Duration::from_secs(4102444800),
+    )
+    .unwrap();
+    assert_eq!(
+        url,
+        "/file/a.txt?exp=4102444800&sig=d80c17a3926f8a28c609a928a63d3de7a4e24a010295fec255e0a4805f06120e"
+    );
+    let (status, body) = request(&server, Method::GET, &url, "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "a");
+
+    let expired = signed_url(
+        "secret",
+        "a.txt",
+        SystemTime::now() - Duration::from_secs(1),
+    );
+    let forged = signed_url(
+        "guessed",
+        "a.txt",
+        UNIX_EPOCH + Duration::from_secs(4102444800),
+    );
+    let other = url.replace("a.txt", "b.txt");
+    for url in [
+        expired.unwrap(),
+        forged.unwrap(),
+        other,
+        String::from("/file/a.txt"),
+    ] {
+        let (status, body) = request(&server, Method::GET, &url, "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", url);
+        assert_eq!(json(&body)["code"], "forbidden");
+    }
+
+    // checksums and existence are signed for the name of the file
+    for route in ["checksum", "exists"] {
+        let path = format!("/file/a.txt/{}", route);
+        let (status, _) = request(&server, Method::GET, &path, "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", path);
+        let signed = url.replace("a.txt?", &format!("a.txt/{}?", route));
+        let (status, _) = request(&server, Method::GET, &signed, "").await;
+        assert_eq!(status, StatusCode::OK, "{}", signed);
+    }
+}
+
+#[tokio::test]
+async fn webhook() {
+    // receives a single notification and passes on its body, along with the status line of
+    // downloading the announced file right away
+    let hook = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let hook_addr = hook.local_addr().unwrap();
+    let server_addr = Arc::new(std::sync::OnceLock::new());
+    let (tx, rx) = oneshot::channel::<(Bytes, String)>();
+    let hook_server_addr = server_addr.clone();
+    tokio::spawn(async move {
+        let (stream, _) = hook.accept().await.unwrap();
+        let tx = Mutex::new(Some(tx));
+        let service = hyper::service::service_fn(|req: Request<hyper::body::Incoming>| {
+            let tx = tx.lock().unwrap().take();
+            let server_addr = *hook_server_addr.get().unwrap();
+            async move {
+                let body = req.into_body().collect().await?.to_bytes();
+                let mut stream = TcpStream::connect(server_addr).await.unwrap();
+                stream
+                    .write_all(
+                        b"GET /file/hello.txt HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+                    )
+                    .await
+                    .unwrap();
+                let mut download = Vec::new();
+                stream.read_to_end(&mut download).await.unwrap();
+                let status = String::from_utf8_lossy(&download)
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                if let Some(tx) = tx {
+                    let _ = tx.send((body, status));
+                }
+                Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::new())))
+            }
+        });
+        let _ = hyper::server::conn::http1::Builder::new()
+            .serve_connection(TokioIo::new(stream), service)
+            .await;
+    });
+
+    let server = start_with(Config {
+        webhook_url: Some(format!("http://{}/hook", hook_addr).parse().unwrap()),
+        ..Config::default()
+    })
+    .await;
+    server_addr.set(server.addr).unwrap();
+    let (status, _) = request(&server, Method::POST, "/file/hello.txt", "hello world").await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (body, download) = tokio::time::timeout(Duration::from_secs(5), rx)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(download, "HTTP/1.1 200 OK");
+    let body = json(&body);
+    assert_eq!(body["name"], "hello.txt");
+    assert_eq!(body["size"], 11);
+    assert_eq!(
+        body["sha256"],
+        "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+    );
+}
+
+#[tokio::test]
+async fn events() {
+    let server = start().await;
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nhost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    // read_until reads from the stream until the data read so far contains needle
+    let mut received = Vec::new();
+    let mut read_until = async |needle: &str| {
+        while !String::from_utf8_lossy(&received).contains(needle) {
+            let mut buf = [0; 1024];
+            let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(read > 0, "stream closed early");
+            received.extend_from_slice(&buf[..read]);
+        }
+    };
+    read_until("text/event-stream").await;
+
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    request(&server, Method::PUT, "/file/a.txt", "b").await;
+    request(&server, Method::DELETE, "/file/a.txt", "").await;
+    read_until("event: deleted").await;
+    let received = String::from_utf8_lossy(&received);
+    let uploaded = received
+        .find("event: uploaded\ndata: {\"action\":\"uploaded\",\"name\":\"a.txt\"}\n\n")
+        .unwrap();
+    let updated = received.find("event: updated\n").unwrap();
+    assert!(uploaded < updated && updated < received.find("event: deleted\n").unwrap());
+}
+
+#[tokio::test]
+async fn idle_event_streams_stay_open() {
+    let server = start_with(Config {
+        idle_timeout: Some(Duration::from_millis(100)),
+        ..Config::default()
+    })
+    .await;
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nhost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    // keep-alive comments are sent often enough that several idle timeouts pass without a close
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    let mut received = Vec::new();
+    while !String::from_utf8_lossy(&received).contains("event: uploaded") {
+        let mut buf = [0; 1024];
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(read > 0, "stream closed early");
+        received.extend_from_slice(&buf[..read]);
+    }
+}
+
+/// PanickingBackend holds a single file a.txt, which can be loaded but panics once opened
+struct PanickingBackend;
+
+impl StorageBackend for PanickingBackend {
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<Entry>>> {
+        Box::pin(async {
+            Ok(vec![Entry {
+                key: String::from("a.txt"),
+                size: 1,
+                modified: SystemTime::UNIX_EPOCH,
+            }])
+        })
+    }
+
+    fn read<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<Bytes>> {
+        Box::pin(async { Ok(Bytes::from("a")) })
+    }
+
+    fn open<'a>(&'a self, _: &'a str, _: u64) -> BoxFuture<'a, io::Result<Reader>> {
+        panic!("opened a.txt")
+    }
+
+    fn write<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<Box<dyn StorageWriter>>> {
+        Box::pin(async { Err(io::ErrorKind::Unsupported.into()) })
+    }
+
+    fn delete<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async { Err(io::ErrorKind::NotFound.into()) })
+    }
+
+    fn exists<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<bool>> {
+        Box::pin(async { Ok(true) })
+    }
+}
+
+#[tokio::test]
+async fn panics_fail_the_request() {
+    let server = start_with(Config {
+        storage: Some(Arc::new(PanickingBackend)),
+        // keeps a.txt out of memory, so downloads open it
+        cache_max_size: 0,
+        ..Config::default()
+    })
+    .await;
+
+    let (status, body) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(json(&body)["code"], "internal");
+    let (status, _) = request(&server, Method::GET, "/health", "").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn list_names() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/b.txt", "bb").await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+
+    let (status, body) = request(&server, Method::GET, "/files?fields=name", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let body = json(&body);
+    assert_eq!(body["names"], serde_json::json!(["a.txt", "b.txt"]));
+    assert_eq!(body["total"], 2);
+    assert!(body.get("files").is_none());
+
+    let req = builder(&server, Method::GET, "/files?fields=name&sort=size")
+        .header("accept", "application/x-ndjson");
+    let res = send(&server, req, "").await;
+    assert_eq!(res.body(), "\"a.txt\"\n\"b.txt\"\n");
+
+    let (status, _) = request(&server, Method::GET, "/files?fields=size", "").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn http_settings() {
+    let server = start_with(Config {
+        keep_alive: false,
+        max_headers: 4,
+        ..Config::default()
+    })
+    .await;
+    let res = send(&server, builder(&server, Method::GET, "/health"), "").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["connection"], "close");
+
+    let mut req = builder(&server, Method::GET, "/health");
+    for i in 0..8 {
+        req = req.header(format!("x-header-{}", i), "value");
+    }
+    let res = send(&server, req, "").await;
+    assert_eq!(res.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn handler_errors_are_answered() {
+    let server = start_with(Config {
+        cache_max_size: 0,
+        ..Config::default()
+    })
+    .await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    // downloading fails once the file is gone from disk behind the store's back
+    std::fs::remove_file(server.store_dir.join("a.txt")).unwrap();
+
+    let (status, body) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(json(&body)["code"], "internal");
+}
+
+#[tokio::test]
+async fn delete_file_gone_from_disk() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    std::fs::remove_file(server.store_dir.join("a.txt")).unwrap();
+
+    let (status, _) = request(&server, Method::DELETE, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn upload_field_aliases() {
+    let server = start().await;
+    let (status, _) = request(&server, Method::POST, "/file", "filename=a.txt&file=form").await;
+    assert_eq!(status, StatusCode::CREATED);
+    // name and content take precedence over their aliases
+    let (status, _) = request(
+        &server,
+        Method::POST,
+        "/file",
+        "filename=x.txt&name=b.txt&content=form&file=x",
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let req = builder(&server, Method::POST, "/file").header("content-type", "application/json");
+    let res = send(&server, req, r#"{"filename":"c.txt","file":"json"}"#).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+
+    for (name, content) in [("a.txt", "form"), ("b.txt", "form"), ("c.txt", "json")] {
+        let (status, body) = request(&server, Method::GET, &format!("/file/{}", name), "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, content);
+    }
+    let (status, _) = request(&server, Method::GET, "/file/x.txt", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn dry_run() {
+    let server = start_with(Config {
+        max_file_size: Some(4),
+        ..Config::default()
+    })
+    .await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+
+    for (path, body, expected) in [
+        ("/file/b.txt?dry_run=true", "b", StatusCode::OK),
+        ("/file?dry_run=true", "name=b.txt&content=b", StatusCode::OK),
+        ("/file/a.txt?dry_run=true", "a", StatusCode::CONFLICT),
+        (
+            "/file/b.txt?dry_run=true",
+            "too large",
+            StatusCode::PAYLOAD_TOO_LARGE,
+        ),
+        ("/file/..?dry_run=true", "b", StatusCode::BAD_REQUEST),
+    ] {
+        let (status, _) = request(&server, Method::POST, path, body).await;
+        assert_eq!(status, expected, "{}", path);
+    }
+
+    let req = builder(&server, Method::POST, "/file?dry_run=true")
+        .header("content-type", "multipart/form-data; boundary=b");
+    let body = "--b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"b.txt\"\r\n\r\nbb\r\n--b--\r\n";
+    let res = send(&server, req, body).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = json(res.body());
+    assert_eq!(body["files"][0]["name"], "b.txt");
+    assert_eq!(body["files"][0]["size"], 2);
+
+    // nothing was stored
+    let (status, _) = request(&server, Method::GET, "/file/b.txt", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(!server.store_dir.join("b.txt").exists());
+    let (status, body) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "a");
+}
+
+#[tokio::test]
+async fn every_body_honors_accept() {
+    let server = start().await;
+    for (path, status) in [
+        ("/health", StatusCode::OK),
+        ("/ready", StatusCode::OK),
+        ("/stats", StatusCode::OK),
+        ("/file/a.txt/exists", StatusCode::OK),
+        ("/file/a.txt", StatusCode::NOT_FOUND),
+        ("/nope", StatusCode::NOT_FOUND),
+    ] {
+        let req = builder(&server, Method::GET, path).header("accept", "application/msgpack");
+        let res = send(&server, req, "").await;
+        assert_eq!(res.status(), status, "{}", path);
+        assert_eq!(
+            res.headers()["content-type"],
+            "application/msgpack",
+            "{}",
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn msgpack_encoding() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/a.txt", "a").await;
+    let msgpack =
+        |path| builder(&server, Method::GET, path).header("accept", "application/msgpack");
+
+    let res = send(&server, msgpack("/health"), "").await;
+    assert_eq!(res.body(), &b"\x81\xa6status\xa2ok"[..]);
+
+    // keys are sorted, names and total are left out unless listed
+    let res = send(&server, msgpack("/files?fields=name"), "").await;
+    assert_eq!(
+        res.body(),
+        &b"\x83\xa3msg\xaaGot 1 file\xa5names\x91\xa5a.txt\xa5total\x01"[..]
+    );
+
+    // strings longer than 31 bytes need a length byte of their own
+    let res = send(&server, msgpack("/file/missing.txt"), "").await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let mut expected = b"\x82\xa4code\xa9not_found\xa3msg\xd9\x25".to_vec();
+    expected.extend(b"File 'missing.txt' not found in store");
+    assert_eq!(res.body(), &expected[..]);
+}
+
+/// begin_upload sends the head and all but the last byte of body to path, the upload is held
+/// open until end_upload sends the rest
+async fn begin_upload(
+    server: &Server,
+    request_line: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> TcpStream {
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    let mut head = format!(
+        "{} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\ncontent-length: {}\r\n",
+        request_line,
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream
+        .write_all(&body.as_bytes()[..body.len() - 1])
+        .await
+        .unwrap();
+    stream.flush().await.unwrap();
+    // gives the server time to start streaming the body
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    stream
+}
+
+/// end_upload completes an upload started by begin_upload and returns the raw response
+async fn end_upload(mut stream: TcpStream, body: &str) -> String {
+    stream
+        .write_all(&body.as_bytes()[body.len() - 1..])
+        .await
+        .unwrap();
+    let mut res = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut res))
+        .await
+        .unwrap()
+        .unwrap();
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+#[tokio::test]
+async fn concurrent_creates_conflict() {
+    let server = start_with(Config {
+        cache_max_size: 0,
+        ..Config::default()
+    })
+    .await;
+    let first = begin_upload(&server, "POST /file/a.txt", &[], "first").await;
+    let (status, _) = request(&server, Method::POST, "/file/a.txt", "second").await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // the name was taken while the first upload streamed in
+    let res = end_upload(first, "first").await;
+    assert!(res.starts_with("HTTP/1.1 409"), "{}", res);
+    let (_, body) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(body, "second");
+    assert_eq!(
+        std::fs::read_to_string(server.store_dir.join("a.txt")).unwrap(),
+        "second"
+    );
+    // nothing of the rejected upload is left behind
+    assert_eq!(std::fs::read_dir(&server.store_dir).unwrap().count(), 1);
+}
+
+#[tokio::test]
+async fn concurrent_if_match() {
+    let server = start().await;
+    request(&server, Method::POST, "/file/a.txt", "original").await;
+    let res = send(&server, builder(&server, Method::GET, "/file/a.txt"), "").await;
+    let etag = res.headers()["etag"].to_str().unwrap().to_string();
+
+    let first = begin_upload(&server, "PUT /file/a.txt", &[("if-match", &etag)], "first").await;
+    let req = builder(&server, Method::PUT, "/file/a.txt").header("if-match", &etag);
+    assert_eq!(send(&server, req, "second").await.status(), StatusCode::OK);
+
+    // the etag changed while the first upload streamed in
+    let res = end_upload(first, "first").await;
+    assert!(res.starts_with("HTTP/1.1 412"), "{}", res);
+    let (_, body) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(body, "second");
+    assert_eq!(
+        std::fs::read_to_string(server.store_dir.join("a.txt")).unwrap(),
+        "second"
+    );
+}
+
+#[tokio::test]
+async fn truncated_uploads() {
+    let server = start().await;
+    for path in ["/file/a.txt", "/file"] {
+        let mut stream = TcpStream::connect(server.addr).await.unwrap();
+        let head = format!(
+            "POST {} HTTP/1.1\r\nhost: localhost\r\ncontent-length: 100\r\n\r\n",
+            path
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream
+            .write_all(b"name=a.txt&content=partial")
+            .await
+            .unwrap();
+        // the client goes away before sending the rest of the body
+        stream.shutdown().await.unwrap();
+        let mut res = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut res))
+            .await
+            .unwrap()
+            .unwrap();
+        let res = String::from_utf8_lossy(&res);
+        assert!(res.starts_with("HTTP/1.1 400"), "{}: {}", path, res);
+    }
+
+    let (status, _) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(!server.store_dir.join("a.txt").exists());
+}
+
+#[tokio::test]
+async fn basic_auth() {
+    let server = start_with(Config {
+        // user:pass
+        basic_auth: Some(String::from("dXNlcjpwYXNz")),
+        ..Config::default()
+    })
+    .await;
+    let with = |method, path, credentials: Option<&str>| {
+        let req = builder(&server, method, path);
+        match credentials {
+            Some(credentials) => req.header("authorization", credentials),
+            None => req,
+        }
+    };
+
+    for credentials in [
+        None,
+        Some("Basic dXNlcjp3cm9uZw=="),
+        Some("Basic dXNlcjpwYXNzCg=="),
+        Some("Bearer dXNlcjpwYXNz"),
+    ] {
+        let res = send(&server, with(Method::POST, "/file/a.txt", credentials), "a").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{:?}", credentials);
+        assert_eq!(json(res.body())["code"], "unauthorized");
+        assert_eq!(
+            res.headers()["www-authenticate"],
+            "Basic realm=\"cdn\", charset=\"UTF-8\""
+        );
+    }
+    assert!(!server.store_dir.join("a.txt").exists());
+
+    let authorized = Some("Basic dXNlcjpwYXNz");
+    let res = send(&server, with(Method::POST, "/file/a.txt", authorized), "a").await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    // reads stay public
+    let (status, body) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "a");
+
+    let res = send(&server, with(Method::DELETE, "/file/a.txt", None), "").await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = send(&server, with(Method::DELETE, "/file/a.txt", authorized), "").await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // unless auth_reads is set, which keeps /health public
+    let server = start_with(Config {
+        basic_auth: Some(String::from("dXNlcjpwYXNz")),
+        auth_reads: true,
+        ..Config::default()
+    })
+    .await;
+    let (status, _) = request(&server, Method::GET, "/files", "").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let req = builder(&server, Method::GET, "/files").header("authorization", "Basic dXNlcjpwYXNz");
+    assert_eq!(send(&server, req, "").await.status(), StatusCode::OK);
+    let (status, _) = request(&server, Method::GET, "/health", "").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn bearer_auth() {
+    let server = start_with(Config {
+        api_token: Some(String::from("secret")),
+        ..Config::default()
+    })
+    .await;
+    let with = |method, path, credentials: Option<&str>| {
+        let req = builder(&server, method, path);
+        match credentials {
+            Some(credentials) => req.header("authorization", credentials),
+            None => req,
+        }
+    };
+
+    for credentials in [
+        None,
+        Some("Bearer wrong"),
+        Some("Bearer secre"),
+        Some("Bearer secrets"),
+        Some("Basic secret"),
+        Some("secret"),
+    ] {
+        let res = send(&server, with(Method::POST, "/file/a.txt", credentials), "a").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{:?}", credentials);
+        assert_eq!(json(res.body())["code"], "unauthorized");
+        assert_eq!(res.headers()["www-authenticate"], "Bearer realm=\"cdn\"");
+    }
+    assert!(!server.store_dir.join("a.txt").exists());
+
+    let authorized = Some("Bearer secret");
+    let res = send(&server, with(Method::POST, "/file/a.txt", authorized), "a").await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    // the scheme is case insensitive
+    let res = send(
+        &server,
+        with(Method::PUT, "/file/a.txt", Some("bearer secret")),
+        "b",
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = send(&server, with(Method::PUT, "/file/a.txt", None), "c").await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    // reads stay public
+    let (status, body) = request(&server, Method::GET, "/file/a.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "b");
+
+    let res = send(
+        &server,
+        with(Method::DELETE, "/file/a.txt", Some("Bearer wrong")),
+        "",
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = send(&server, with(Method::DELETE, "/file/a.txt", authorized), "").await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn client() {
+    let server = start_with(Config {
+        api_token: Some(String::from("secret")),
+        ..Config::default()
+    })
+    .await;
+    let client = CdnClient::new(server.addr.to_string()).with_token("secret");
+    client.upload("css/app.css", "body {}").await.unwrap();
+    client.upload("index.html", "<p>").await.unwrap();
+    assert_eq!(client.download("css/app.css").await.unwrap(), "body {}");
+    // names are percent encoded
+    client.upload("my docs/a&b?.txt", "doc").await.unwrap();
+    assert_eq!(client.download("my docs/a&b?.txt").await.unwrap(), "doc");
+    assert_eq!(
+        client.list("my ").await.unwrap().files[0].name,
+        "my docs/a&b?.txt"
+    );
+    client.delete("my docs/a&b?.txt").await.unwrap();
+
+    let listing = client.list("css/").await.unwrap();
+    assert_eq!(listing.total, 1);
+    assert_eq!(listing.files[0].name, "css/app.css");
+    assert_eq!(listing.files[0].size, 7);
+
+    let err = client.upload("index.html", "<p>").await.unwrap_err();
+    let err = err.downcast_ref::<ApiError>().unwrap();
+    assert_eq!(err.status, StatusCode::CONFLICT);
+    assert_eq!(err.code, Some(ErrorCode::Conflict));
+
+    client.delete("index.html").await.unwrap();
+    let err = client.download("index.html").await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ApiError>().unwrap().code,
+        Some(ErrorCode::NotFound)
+    );
+
+    // only writes require the token
+    let anonymous = CdnClient::new(server.addr.to_string());
+    assert_eq!(anonymous.list("").await.unwrap().total, 1);
+    let err = anonymous.delete("css/app.css").await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ApiError>().unwrap().status,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+/// hex decodes the test vectors below, which were compressed with python's gzip module
+fn hex(data: &str) -> Vec<u8> {
+    (0..data.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&data[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+/// send_gzipped POSTs the gzipped body to path, returning the status of the response
+async fn send_gzipped(server: &Server, path: &str, body: &[u8]) -> StatusCode {
+    let stream = TcpStream::connect(server.addr).await.unwrap();
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+    let req = builder(server, Method::POST, path)
+        .header("content-encoding", "gzip")
+        .body(Full::new(Bytes::from(body.to_vec())))
+        .unwrap();
+    sender.send_request(req).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn gzipped_uploads() {
+    let server = start().await;
+    let bottles = (0..100)
+        .map(|i| format!("{} bottles of beer on the wall\n", i % 10))
+        .collect::<String>();
+    // a single block with dynamic huffman codes
+    let dynamic = hex(
+        "1f8b0800000000000203edd0bb0980401004d0dc2aa684f3afe578b087c1e2822ed8be1d4c6a32f1cb5e41\
+         8d4cb707d150cd6ec4853c0defe1def55407aa23d589ea4c75a1ba52dda8ee548bae74a52b5de94a57bad2\
+         95ae74f5d3d507fe286b97b80b0000",
+    );
+    // two members, one made of a stored block and one of a block with fixed huffman codes
+    let members = hex(
+        "1f8b0800000000000403010700f8ff73746f726564203455e87e070000001f8b08000000000002034bcc4b\
+         5148cbac484d01002f67ce4809000000",
+    );
+    assert_eq!(
+        send_gzipped(&server, "/file/bottles.txt", &dynamic).await,
+        StatusCode::CREATED
+    );
+    assert_eq!(
+        send_gzipped(&server, "/file/members.txt", &members).await,
+        StatusCode::CREATED
+    );
+    let (_, body) = request(&server, Method::GET, "/file/bottles.txt", "").await;
+    assert_eq!(body, bottles);
+    let (_, body) = request(&server, Method::GET, "/file/members.txt", "").await;
+    assert_eq!(body, "stored and fixed");
+
+    let status = send_gzipped(&server, "/file/a.txt", b"not gzip").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let status = send_gzipped(&server, "/file/a.txt", &dynamic[..50]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let encoded = |encoding| {
+        builder(&server, Method::POST, "/file/a.txt").header("content-encoding", encoding)
+    };
+    let res = send(&server, encoded("br"), "a").await;
+    assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(json(res.body())["code"], "unsupported_media_type");
+    let res = send(&server, encoded("identity"), "a").await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+
+    // the bottles decompress to more than max_body_size
+    let server = start_with(Config {
+        max_body_size: 2000,
+        ..Config::default()
+    })
+    .await;
+    let status = send_gzipped(&server, "/file/bottles.txt", &dynamic).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn percent_encoded_paths() {
+    let server = start().await;
+    let (status, _) = request(&server, Method::POST, "/file", "name=my+file.txt&content=a").await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, body) = request(&server, Method::GET, "/file/my%20file.txt", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "a");
+
+    // raw uploads store the decoded name
+    let (status, _) = request(&server, Method::POST, "/file/d%C3%A9j%C3%A0/b%20c.txt", "b").await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, body) = request(&server, Method::GET, "/files?prefix=d", "").await;
+    assert_eq!(json(&body)["files"][0]["name"], "déjà/b c.txt");
+
+    for path in [
+        "/file/a%2Fb.txt",
+        "/file/a%00b.txt",
+        "/file/%FF.txt",
+        "/file/%2e%2e/a.txt",
+        "/file/a%5Cb.txt",
+        "/file/.cdn-expiries.json",
+        "/file/%2Ecdn-expiries.json",
+    ] {
+        let (status, _) = request(&server, Method::GET, path, "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn gzipped_downloads() {
+    let server = start().await;
+    let text = "all work and no play makes jack a dull boy\n".repeat(100);
+    request(&server, Method::POST, "/file/a.txt", &text).await;
+    // larger than what is compressed
+    let large = "x".repeat(2 * 1024 * 1024);
+    request(&server, Method::POST, "/file/large.txt", &large).await;
+
+    let gzip = |path| builder(&server, Method::GET, path).header("accept-encoding", "gzip");
+    let res = send(&server, gzip("/file/a.txt"), "").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-encoding"], "gzip");
+    assert_eq!(res.headers()["vary"], "accept-encoding");
+    assert_eq!(
+        res.headers()["content-length"],
+        res.body().len().to_string()
+    );
+    assert!(res.body().len() < text.len() / 10);
+    // the server's own decoder inflates what its encoder produced
+    assert_eq!(
+        send_gzipped(&server, "/file/inflated.txt", res.body()).await,
+        StatusCode::CREATED
+    );
+    let (_, body) = request(&server, Method::GET, "/file/inflated.txt", "").await;
+    assert_eq!(body, text);
+
+    let res = send(&server, gzip("/file/large.txt"), "").await;
+    assert!(res.headers().get("content-encoding").is_none());
+    assert_eq!(res.body().len(), large.len());
+    let res = send(&server, builder(&server, Method::GET, "/file/a.txt"), "").await;
+    assert!(res.headers().get("content-encoding").is_none());
+    assert_eq!(res.body(), text.as_str());
+}